@@ -0,0 +1,2486 @@
+use std::{
+    cmp,
+    collections::{BTreeMap, BTreeSet, HashSet, VecDeque},
+    env,
+    error::Error,
+    ffi::CString,
+    fmt, fs, io, mem,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use bytemuck::{Pod, Zeroable};
+use cgmath::{Deg, Matrix4, Point3, Rad, Vector3};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer, DeviceLocalBuffer, TypedBufferAccess},
+    command_buffer::{
+        AutoCommandBufferBuilder, CommandBufferUsage, PrimaryAutoCommandBuffer,
+        PrimaryCommandBuffer, SubpassContents,
+    },
+    descriptor_set::{
+        layout::{
+            DescriptorSetLayout, DescriptorSetLayoutBinding, DescriptorSetLayoutCreateInfo,
+            DescriptorType,
+        },
+        PersistentDescriptorSet, WriteDescriptorSet,
+    },
+    device::{
+        physical::{PhysicalDevice, PhysicalDeviceType},
+        Device, DeviceCreationError, Queue,
+    },
+    format::{ClearValue, Format},
+    image::{
+        view::{ImageView, ImageViewAbstract},
+        AttachmentImage, ImageDimensions, ImageUsage, ImmutableImage, MipmapsCount, SampleCount,
+        SwapchainImage,
+    },
+    instance::{
+        debug::{DebugUtilsMessageSeverity, DebugUtilsMessenger},
+        Instance, InstanceCreationError,
+    },
+    pipeline::{
+        graphics::{
+            depth_stencil::DepthStencilState,
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::{CullMode, FrontFace, RasterizationState},
+            vertex_input::BuffersDefinition,
+            viewport::{Scissor, Viewport, ViewportState},
+            GraphicsPipelineCreationError,
+        },
+        layout::{PipelineLayout, PipelineLayoutCreateInfo, PushConstantRange},
+        GraphicsPipeline, Pipeline, PipelineBindPoint,
+    },
+    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
+    sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+    shader::{ShaderModule, ShaderStages},
+    swapchain::{
+        acquire_next_image, AcquireError, ColorSpace, PresentMode, Surface, SurfaceTransform,
+        Swapchain, SwapchainCreateInfo, SwapchainCreationError,
+    },
+    sync::{self, FenceSignalFuture, FlushError, GpuFuture},
+    DeviceSize,
+};
+use vulkano_win::{CreationError, VkSurfaceBuild};
+use winit::{
+    dpi::LogicalSize,
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
+    event_loop::{ControlFlow, EventLoop},
+    monitor::MonitorHandle,
+    window::{Window, WindowBuilder},
+};
+
+use crate::{
+    device::{self, FormatCapabilities, RequiredLimits},
+    instance::{self, ValidationFeatureConfig},
+    swapchain,
+};
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+struct Vertex {
+    position: [f32; 2],
+    color: [f32; 3],
+    tex_coord: [f32; 2],
+}
+vulkano::impl_vertex!(Vertex, position, color, tex_coord);
+
+const VERTICES: [Vertex; 4] = [
+    Vertex {
+        position: [-0.5, -0.5],
+        color: [1.0, 0.0, 0.0],
+        tex_coord: [1.0, 0.0],
+    },
+    Vertex {
+        position: [0.5, -0.5],
+        color: [0.0, 1.0, 0.0],
+        tex_coord: [0.0, 0.0],
+    },
+    Vertex {
+        position: [0.5, 0.5],
+        color: [0.0, 0.0, 1.0],
+        tex_coord: [0.0, 1.0],
+    },
+    Vertex {
+        position: [-0.5, 0.5],
+        color: [1.0, 1.0, 1.0],
+        tex_coord: [1.0, 1.0],
+    },
+];
+
+const INDICES: [u16; 6] = [0, 1, 2, 2, 3, 0];
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+struct UniformBufferObject {
+    model: [[f32; 4]; 4],
+    view: [[f32; 4]; 4],
+    proj: [[f32; 4]; 4],
+}
+
+// Placement of one copy of the mesh, applied after the animated model matrix from the UBO
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+struct PushConstants {
+    model: [[f32; 4]; 4],
+}
+
+// Vulkan guarantees at least 128 bytes of push constants on every device
+const _: () = assert!(mem::size_of::<PushConstants>() <= 128);
+
+// Each offset draws another copy of the quad from the same command buffer
+const INSTANCE_OFFSETS: [[f32; 3]; 2] = [[-0.6, 0.0, 0.0], [0.6, 0.0, 0.0]];
+
+mod vertex_shader {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/shaders/triangle.vert"
+    }
+}
+
+mod fragment_shader {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/shaders/triangle.frag"
+    }
+}
+
+#[derive(Debug)]
+pub enum AppError {
+    VulkanUnavailable(String),
+    InstanceCreation(InstanceCreationError),
+    WindowCreation(CreationError),
+    // One "device: reason" entry per rejected device
+    NoSuitableDevice(Vec<String>),
+    DeviceCreation(DeviceCreationError),
+    SwapchainCreation(SwapchainCreationError),
+    MissingSurfaceExtensions(Vec<String>),
+    UnusableSurfaceExtent([u32; 2]),
+    PresentQueueUnsupported(u32),
+    ShaderRead(PathBuf, io::Error),
+    InvalidSpirv(PathBuf, String),
+    UnsupportedSwapchainFormat(Format, ColorSpace),
+    UnsupportedSwapchainUsage(Vec<&'static str>),
+    UnsupportedSurfaceTransform(SurfaceTransform),
+    MissingQueueFamily(&'static str),
+    InvalidQueueFamily(u32),
+    PipelineCreation(GraphicsPipelineCreationError),
+    NoDepthFormat,
+    TextureLoad(PathBuf, image::ImageError),
+    ForeignSurface,
+    SwapchainAcquire(AcquireError),
+    Present(FlushError),
+    NoEventLoop,
+    SurfaceExtensionsNotEnabled(Vec<String>),
+    UnsupportedScreenshotFormat(Format),
+    ScreenshotSave(PathBuf, image::ImageError),
+    EmptyUpload,
+    UploadTooLarge(DeviceSize, DeviceSize),
+    UploadFailed(String),
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AppError::VulkanUnavailable(reason) => {
+                write!(f, "Couldn't load the Vulkan library: {}", reason)
+            }
+            AppError::InstanceCreation(err) => write!(f, "Couldn't create instance: {}", err),
+            AppError::WindowCreation(err) => write!(f, "Couldn't create window: {}", err),
+            AppError::NoSuitableDevice(rejections) if rejections.is_empty() => {
+                write!(f, "No Vulkan physical devices were found")
+            }
+            AppError::NoSuitableDevice(rejections) => write!(
+                f,
+                "No physical device can run the app: {}",
+                rejections.join("; ")
+            ),
+            AppError::DeviceCreation(err) => write!(f, "Couldn't create device: {}", err),
+            AppError::SwapchainCreation(err) => write!(f, "Couldn't create swapchain: {}", err),
+            AppError::MissingSurfaceExtensions(missing) => write!(
+                f,
+                "Surface extensions required to open a window are not supported: {}. \
+                 This usually means no display server is reachable (e.g. in Docker or CI); \
+                 start or install X11/Wayland and make it available to the process",
+                missing.join(", ")
+            ),
+            AppError::UnusableSurfaceExtent([width, height]) => write!(
+                f,
+                "The surface reports no usable extent for a {}x{} window",
+                width, height
+            ),
+            AppError::PresentQueueUnsupported(family_id) => write!(
+                f,
+                "The present queue's family {} can't present to the window surface",
+                family_id
+            ),
+            AppError::ShaderRead(path, err) => {
+                write!(f, "Couldn't read shader {}: {}", path.display(), err)
+            }
+            AppError::InvalidSpirv(path, reason) => {
+                write!(f, "{} is not valid SPIR-V: {}", path.display(), reason)
+            }
+            AppError::UnsupportedSwapchainFormat(format, color_space) => write!(
+                f,
+                "The surface doesn't support the forced swapchain format {:?} / {:?}",
+                format, color_space
+            ),
+            AppError::UnsupportedSwapchainUsage(flags) => write!(
+                f,
+                "The surface doesn't support swapchain image usage {}",
+                flags.join(", ")
+            ),
+            AppError::UnsupportedSurfaceTransform(transform) => write!(
+                f,
+                "The surface doesn't support the requested transform {:?}",
+                transform
+            ),
+            AppError::MissingQueueFamily(kind) => {
+                write!(f, "No {} queue family was selected for the device", kind)
+            }
+            AppError::InvalidQueueFamily(id) => {
+                write!(f, "Queue family {} doesn't exist on the device", id)
+            }
+            AppError::PipelineCreation(err) => {
+                write!(f, "Couldn't create graphics pipeline: {}", err)
+            }
+            AppError::SwapchainAcquire(err) => {
+                write!(f, "Couldn't acquire swapchain image: {}", err)
+            }
+            AppError::Present(err) => write!(f, "Couldn't present swapchain image: {}", err),
+            AppError::NoEventLoop => write!(
+                f,
+                "The app has no event loop; apps built with with_surface must call handle_event"
+            ),
+            AppError::ForeignSurface => {
+                write!(f, "The surface was created from a different instance")
+            }
+            AppError::SurfaceExtensionsNotEnabled(missing) => write!(
+                f,
+                "The instance doesn't enable the surface extensions needed to present: {}",
+                missing.join(", ")
+            ),
+            AppError::TextureLoad(path, err) => {
+                write!(f, "Couldn't load texture {}: {}", path.display(), err)
+            }
+            AppError::UnsupportedScreenshotFormat(format) => {
+                write!(f, "Can't read back {:?} images as RGBA8", format)
+            }
+            AppError::ScreenshotSave(path, err) => {
+                write!(f, "Couldn't save screenshot {}: {}", path.display(), err)
+            }
+            AppError::EmptyUpload => write!(f, "Can't upload an empty buffer"),
+            AppError::UploadFailed(reason) => write!(f, "Couldn't upload buffer: {}", reason),
+            AppError::UploadTooLarge(len, capacity) => write!(
+                f,
+                "Upload of {} elements doesn't fit a buffer of {}",
+                len, capacity
+            ),
+            AppError::NoDepthFormat => {
+                write!(
+                    f,
+                    "The device supports none of the depth attachment formats"
+                )
+            }
+        }
+    }
+}
+
+impl Error for AppError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AppError::InstanceCreation(err) => Some(err),
+            AppError::WindowCreation(err) => Some(err),
+            AppError::DeviceCreation(err) => Some(err),
+            AppError::SwapchainCreation(err) => Some(err),
+            AppError::ShaderRead(_, err) => Some(err),
+            AppError::PipelineCreation(err) => Some(err),
+            AppError::TextureLoad(_, err) => Some(err),
+            AppError::ScreenshotSave(_, err) => Some(err),
+            AppError::SwapchainAcquire(err) => Some(err),
+            AppError::Present(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<InstanceCreationError> for AppError {
+    fn from(err: InstanceCreationError) -> Self {
+        AppError::InstanceCreation(err)
+    }
+}
+
+impl From<CreationError> for AppError {
+    fn from(err: CreationError) -> Self {
+        AppError::WindowCreation(err)
+    }
+}
+
+impl From<DeviceCreationError> for AppError {
+    fn from(err: DeviceCreationError) -> Self {
+        AppError::DeviceCreation(err)
+    }
+}
+
+impl From<SwapchainCreationError> for AppError {
+    fn from(err: SwapchainCreationError) -> Self {
+        AppError::SwapchainCreation(err)
+    }
+}
+
+impl From<GraphicsPipelineCreationError> for AppError {
+    fn from(err: GraphicsPipelineCreationError) -> Self {
+        AppError::PipelineCreation(err)
+    }
+}
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+// Reads a precompiled `.spv` file (e.g. from glslc) so shaders can change without a rebuild
+fn load_shader_module(device: &Arc<Device>, path: &Path) -> Result<Arc<ShaderModule>, AppError> {
+    let bytes = fs::read(path).map_err(|err| AppError::ShaderRead(path.to_owned(), err))?;
+    validate_spirv(path, &bytes)?;
+
+    unsafe { ShaderModule::from_bytes(device.clone(), &bytes) }
+        .map_err(|err| AppError::InvalidSpirv(path.to_owned(), err.to_string()))
+}
+
+// A reloaded module can be valid SPIR-V and still lack the entry point the pipeline uses
+fn missing_entry_point(path: Option<&Path>) -> AppError {
+    let path = path.unwrap_or_else(|| Path::new("<embedded shader>"));
+    AppError::InvalidSpirv(path.to_owned(), String::from("no \"main\" entry point"))
+}
+
+fn validate_spirv(path: &Path, bytes: &[u8]) -> Result<(), AppError> {
+    if bytes.len() < 20 || bytes.len() % 4 != 0 {
+        return Err(AppError::InvalidSpirv(
+            path.to_owned(),
+            format!("{} bytes is not a whole SPIR-V module", bytes.len()),
+        ));
+    }
+
+    let magic = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    if magic != SPIRV_MAGIC {
+        return Err(AppError::InvalidSpirv(
+            path.to_owned(),
+            format!("bad magic number {:#010x}", magic),
+        ));
+    }
+
+    Ok(())
+}
+
+fn upload_error(err: impl fmt::Display) -> AppError {
+    AppError::UploadFailed(err.to_string())
+}
+
+// Uploads arbitrarily large data to device-local buffers through one bounded staging buffer,
+// copying and waiting chunk by chunk so host-visible memory use never exceeds `chunk_len`
+// elements.
+struct StreamingUploader<T: Pod + Send + Sync> {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    staging_buffer: Arc<CpuAccessibleBuffer<[T]>>,
+    chunk_len: DeviceSize,
+}
+
+impl<T: Pod + Send + Sync> StreamingUploader<T> {
+    fn new(device: Arc<Device>, queue: Arc<Queue>, chunk_len: DeviceSize) -> Self {
+        let staging_buffer = unsafe {
+            CpuAccessibleBuffer::uninitialized_array(
+                device.clone(),
+                chunk_len,
+                BufferUsage::transfer_src(),
+                false,
+            )
+            .expect("Couldn't create staging buffer")
+        };
+
+        Self {
+            device,
+            queue,
+            staging_buffer,
+            chunk_len,
+        }
+    }
+
+    fn upload(
+        &self,
+        data: &[T],
+        destination: &Arc<DeviceLocalBuffer<[T]>>,
+    ) -> Result<(), AppError> {
+        if data.is_empty() {
+            return Err(AppError::EmptyUpload);
+        }
+        if data.len() as DeviceSize > destination.len() {
+            return Err(AppError::UploadTooLarge(
+                data.len() as DeviceSize,
+                destination.len(),
+            ));
+        }
+
+        for (index, chunk) in data.chunks(self.chunk_len as usize).enumerate() {
+            {
+                let mut staging = self.staging_buffer.write().expect("Staging buffer still in use");
+                staging[..chunk.len()].copy_from_slice(chunk);
+            }
+
+            let mut builder = AutoCommandBufferBuilder::primary(
+                self.device.clone(),
+                self.queue.family(),
+                CommandBufferUsage::OneTimeSubmit,
+            )
+            .map_err(upload_error)?;
+            builder
+                .copy_buffer_dimensions(
+                    self.staging_buffer.clone(),
+                    0,
+                    destination.clone(),
+                    index as DeviceSize * self.chunk_len,
+                    chunk.len() as DeviceSize,
+                )
+                .map_err(upload_error)?;
+
+            // The staging buffer is reused for the next chunk, so wait for this copy to finish
+            builder
+                .build()
+                .map_err(upload_error)?
+                .execute(self.queue.clone())
+                .map_err(upload_error)?
+                .then_signal_fence_and_flush()
+                .map_err(upload_error)?
+                .wait(None)
+                .map_err(upload_error)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Wall-clock time spent in each phase of `draw_frame`. A consistently high `acquire` means the
+// CPU is waiting on the GPU/compositor for a free image rather than doing useful work.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameTimings {
+    pub acquire: Duration,
+    pub submit_present: Duration,
+}
+
+// Frame rate and average frame time over the last second, shown in the window title
+struct FrameStats {
+    frames: VecDeque<Instant>,
+    last_report: Instant,
+}
+
+impl FrameStats {
+    fn new() -> Self {
+        Self {
+            frames: VecDeque::new(),
+            last_report: Instant::now(),
+        }
+    }
+
+    // Records a frame presented at `now`, returning a summary once `interval` has passed since
+    // the previous one
+    fn record(&mut self, now: Instant, interval: Duration) -> Option<String> {
+        self.frames.push_back(now);
+        while let Some(&oldest) = self.frames.front() {
+            if now.duration_since(oldest) <= Duration::from_secs(1) {
+                break;
+            }
+            self.frames.pop_front();
+        }
+
+        if now.duration_since(self.last_report) < interval || self.frames.len() < 2 {
+            return None;
+        }
+        self.last_report = now;
+
+        let frame_time = now.duration_since(self.frames[0]) / (self.frames.len() as u32 - 1);
+        Some(format!(
+            "{:.0} fps ({:.1} ms)",
+            1.0 / frame_time.as_secs_f64(),
+            frame_time.as_secs_f64() * 1000.0
+        ))
+    }
+}
+
+pub struct HelloTriangleApplication {
+    instance: Arc<Instance>,
+    physical_device_index: usize,
+    logical_device: Arc<Device>,
+    graphics_queue: Arc<Queue>,
+    present_queue: Arc<Queue>,
+    compute_queue: Arc<Queue>,
+    swapchain: Arc<Swapchain<Window>>,
+    images: Vec<Arc<SwapchainImage<Window>>>,
+    image_format: Format,
+    image_extent: [u32; 2],
+    render_pass: Arc<RenderPass>,
+    depth_format: Format,
+    msaa_samples: SampleCount,
+    // Set on resize; the swapchain and everything sized by it are rebuilt before the next frame
+    swapchain_dirty: bool,
+    // Set while the surface extent is zero and the swapchain can't be recreated
+    minimized: bool,
+    graphics_pipeline: Arc<GraphicsPipeline>,
+    framebuffers: Vec<Arc<Framebuffer>>,
+    command_buffers: Vec<Arc<PrimaryAutoCommandBuffer>>,
+    // Fence of the last submission made from each frame slot, None until the slot is first used
+    frames_in_flight: Vec<Option<FrameFence>>,
+    // Frame slot that last rendered to each swapchain image
+    images_in_flight: Vec<Option<usize>>,
+    current_frame: usize,
+    vertex_buffer: Arc<DeviceLocalBuffer<[Vertex]>>,
+    index_buffer: Arc<DeviceLocalBuffer<[u16]>>,
+    uniform_buffers: Vec<Arc<CpuAccessibleBuffer<UniformBufferObject>>>,
+    descriptor_set_layout: Arc<DescriptorSetLayout>,
+    descriptor_sets: Vec<Arc<PersistentDescriptorSet>>,
+    texture_image: Arc<ImageView<ImmutableImage>>,
+    texture_sampler: Arc<Sampler>,
+    start_time: Instant,
+    frame_timings: FrameTimings,
+    frame_stats: FrameStats,
+    accumulated_timings: FrameTimings,
+    timed_frames: u32,
+    debug_callback: Option<DebugUtilsMessenger>,
+    event_loop: Option<EventLoop<()>>,
+    // False for surfaces handed in through with_surface, whose window belongs to the host
+    owns_window: bool,
+    // First error that stopped rendering; the event loop exits and `run` returns it
+    fatal_error: Option<AppError>,
+    surface: Arc<Surface<Window>>,
+    clear_via_transfer: bool,
+    paused: bool,
+    // Whether the pause came from losing focus, so regaining it doesn't undo a manual pause
+    paused_by_focus: bool,
+    // Set by the shader watcher when a configured .spv file changes
+    pipeline_dirty: Arc<AtomicBool>,
+    _shader_watcher: Option<RecommendedWatcher>,
+    // Minimum time between frames derived from `config.frame_cap`, None when uncapped
+    frame_interval: Option<Duration>,
+    current_monitor: Option<MonitorHandle>,
+    last_frame: Instant,
+    config: AppConfig,
+}
+
+// Set to 1/0 (or true/false) to force validation on or off regardless of the build profile
+const VALIDATION_ENV: &str = "VK_APP_VALIDATION";
+
+// Comma-separated validation feature modes to enable: gpu-assisted, best-practices, sync
+const VALIDATION_FEATURES_ENV: &str = "VK_APP_VALIDATION_FEATURES";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameCap {
+    Uncapped,
+    // Caps to the refresh rate of the monitor showing the window, less `margin_hz`
+    MonitorRefresh { margin_hz: u32 },
+}
+
+type FrameFence = Arc<FenceSignalFuture<Box<dyn GpuFuture>>>;
+
+// Sees every winit event before the renderer does. Returning true consumes the event, so the
+// built-in handling (close, resize, redraw, hotkeys) is skipped for it.
+pub type EventHandler = Box<dyn FnMut(&Event<()>, &mut ControlFlow) -> bool>;
+
+pub struct AppConfig {
+    pub validation: bool,
+    pub validation_features: ValidationFeatureConfig,
+    // Which validation messages get printed; F12 re-enables messages with the same mask
+    pub debug_severity: DebugUtilsMessageSeverity,
+    // Tried in order, the first one the surface supports wins; Fifo is the guaranteed fallback
+    pub present_modes: Vec<PresentMode>,
+    pub event_handler: Option<EventHandler>,
+    // Bypasses the format preference entirely; creation fails if the surface lacks this pair
+    pub force_swapchain_format: Option<(Format, ColorSpace)>,
+    // Rotation the presentation engine applies to our images, e.g. for rotated kiosk displays.
+    // Mirrored transforms are rejected. None keeps the surface's current transform, or Identity
+    // if that one is mirrored.
+    pub surface_transform: Option<SurfaceTransform>,
+    pub frame_cap: FrameCap,
+    // Precompiled SPIR-V to use instead of the shaders embedded at build time
+    pub vertex_shader_path: Option<PathBuf>,
+    pub fragment_shader_path: Option<PathBuf>,
+    // PNG/JPEG mapped onto the quad; without one the vertex colors are drawn unchanged
+    pub texture_path: Option<PathBuf>,
+    // MSAA sample count to aim for, lowered to what the device supports; Sample1 disables it
+    pub msaa_samples: SampleCount,
+    // Upper bound on texture anisotropy, lowered to the device maximum; 1.0 disables it
+    pub max_anisotropy: f32,
+    // Window title; the frame rate is appended to it every `stats_interval`, off by default.
+    // Windows passed in through `with_surface` keep their title either way.
+    pub title: String,
+    pub stats_interval: Option<Duration>,
+    // Stops rendering while the window is unfocused, resuming when focus returns
+    pub pause_on_focus_loss: bool,
+    // Initial inner size of the window in logical pixels
+    pub size: [u32; 2],
+    // A fixed-size window only sees resizes from outside, e.g. a DPI change or the compositor
+    pub resizable: bool,
+    pub decorations: bool,
+    // A hidden window still gets a surface, for rendering offscreen without flashing a window
+    pub visible: bool,
+    // Among suitable devices, one of this type wins over the usual ranking
+    pub device_preference: Option<PhysicalDeviceType>,
+    // Minimum device limits; devices below any of them are never picked
+    pub required_limits: RequiredLimits,
+    // Usage of the swapchain images; color_attachment is always added. Creation fails if the
+    // surface doesn't support one of the requested flags.
+    pub swapchain_image_usage: ImageUsage,
+    // Fills each frame with a transfer clear instead of drawing, which adds transfer_dst to
+    // `swapchain_image_usage`
+    pub clear_via_transfer: bool,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            validation: cfg!(debug_assertions),
+            validation_features: ValidationFeatureConfig::default(),
+            debug_severity: DebugUtilsMessageSeverity {
+                error: true,
+                warning: true,
+                information: false,
+                verbose: false,
+            },
+            present_modes: vec![PresentMode::Mailbox, PresentMode::Fifo],
+            event_handler: None,
+            force_swapchain_format: None,
+            surface_transform: None,
+            frame_cap: FrameCap::Uncapped,
+            vertex_shader_path: None,
+            fragment_shader_path: None,
+            texture_path: None,
+            msaa_samples: SampleCount::Sample4,
+            max_anisotropy: 16.0,
+            title: String::from("My Vulkan Triangle"),
+            stats_interval: None,
+            pause_on_focus_loss: false,
+            size: [1280, 720],
+            resizable: true,
+            decorations: true,
+            visible: true,
+            device_preference: None,
+            required_limits: RequiredLimits::default(),
+            swapchain_image_usage: ImageUsage {
+                color_attachment: true,
+                ..ImageUsage::none()
+            },
+            clear_via_transfer: false,
+        }
+    }
+}
+
+impl AppConfig {
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Ok(value) = env::var(VALIDATION_ENV) {
+            match value.to_lowercase().as_str() {
+                "1" | "true" | "on" => config.validation = true,
+                "0" | "false" | "off" => config.validation = false,
+                _ => println!("Ignoring unrecognized {}={}", VALIDATION_ENV, value),
+            }
+        }
+        if let Ok(value) = env::var(VALIDATION_FEATURES_ENV) {
+            for mode in value.split(',').map(str::trim).filter(|mode| !mode.is_empty()) {
+                match mode {
+                    "gpu-assisted" => config.validation_features.gpu_assisted = true,
+                    "best-practices" => config.validation_features.best_practices = true,
+                    "sync" => config.validation_features.synchronization = true,
+                    _ => println!(
+                        "Ignoring unrecognized {} mode {}",
+                        VALIDATION_FEATURES_ENV, mode
+                    ),
+                }
+            }
+        }
+        if let Some(dir) = env::var_os(SHADER_DIR_ENV).map(PathBuf::from) {
+            config.vertex_shader_path = Some(dir.join("triangle.vert.spv"));
+            config.fragment_shader_path = Some(dir.join("triangle.frag.spv"));
+        }
+        config.texture_path = env::var_os(TEXTURE_ENV).map(PathBuf::from);
+        config
+    }
+}
+
+pub struct AppBuilder {
+    config: AppConfig,
+}
+
+// Starts from `AppConfig::default`; pass `AppConfig::from_env` to `from_config` to honor the
+// environment overrides
+impl AppBuilder {
+    pub fn new() -> Self {
+        Self::from_config(AppConfig::default())
+    }
+
+    pub fn from_config(config: AppConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn with_size(mut self, width: u32, height: u32) -> Self {
+        self.config.size = [width, height];
+        self
+    }
+
+    pub fn with_resizable(mut self, resizable: bool) -> Self {
+        self.config.resizable = resizable;
+        self
+    }
+
+    pub fn with_decorations(mut self, decorations: bool) -> Self {
+        self.config.decorations = decorations;
+        self
+    }
+
+    pub fn with_visible(mut self, visible: bool) -> Self {
+        self.config.visible = visible;
+        self
+    }
+
+    pub fn with_stats_interval(mut self, stats_interval: Option<Duration>) -> Self {
+        self.config.stats_interval = stats_interval;
+        self
+    }
+
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.config.title = title.into();
+        self
+    }
+
+    pub fn with_validation(mut self, validation: bool) -> Self {
+        self.config.validation = validation;
+        self
+    }
+
+    pub fn with_validation_features(mut self, features: ValidationFeatureConfig) -> Self {
+        self.config.validation_features = features;
+        self
+    }
+
+    pub fn with_debug_severity(mut self, severity: DebugUtilsMessageSeverity) -> Self {
+        self.config.debug_severity = severity;
+        self
+    }
+
+    pub fn with_max_anisotropy(mut self, max_anisotropy: f32) -> Self {
+        self.config.max_anisotropy = max_anisotropy;
+        self
+    }
+
+    pub fn with_swapchain_image_usage(mut self, usage: ImageUsage) -> Self {
+        self.config.swapchain_image_usage = usage;
+        self
+    }
+
+    pub fn with_clear_via_transfer(mut self, clear_via_transfer: bool) -> Self {
+        self.config.clear_via_transfer = clear_via_transfer;
+        self
+    }
+
+    pub fn with_required_limits(mut self, required_limits: RequiredLimits) -> Self {
+        self.config.required_limits = required_limits;
+        self
+    }
+
+    pub fn with_frame_cap(mut self, frame_cap: FrameCap) -> Self {
+        self.config.frame_cap = frame_cap;
+        self
+    }
+
+    // Used when the surface supports it, otherwise Fifo
+    pub fn with_present_mode(mut self, present_mode: PresentMode) -> Self {
+        self.config.present_modes = vec![present_mode];
+        self
+    }
+
+    pub fn with_device_preference(mut self, device_type: PhysicalDeviceType) -> Self {
+        self.config.device_preference = Some(device_type);
+        self
+    }
+
+    pub fn build(self) -> Result<HelloTriangleApplication, AppError> {
+        HelloTriangleApplication::with_config(self.config)
+    }
+}
+
+impl Default for AppBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+const CLEAR_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+// Directory of precompiled `.spv` shaders; the shaders embedded at build time are used if unset
+const SHADER_DIR_ENV: &str = "VK_APP_SHADER_DIR";
+// Image file to texture the quad with
+const TEXTURE_ENV: &str = "VK_APP_TEXTURE";
+
+// Frames averaged per line of the frame timing diagnostic
+const TIMING_REPORT_INTERVAL: u32 = 300;
+
+// Tried in order; the first usable as an optimally tiled depth attachment is picked
+const DEPTH_FORMATS: [Format; 3] = [
+    Format::D32_SFLOAT,
+    Format::D32_SFLOAT_S8_UINT,
+    Format::D24_UNORM_S8_UINT,
+];
+
+// Frames the CPU may record and submit ahead of the GPU
+const MAX_FRAMES_IN_FLIGHT: usize = 2;
+
+// Upper bound in bytes on the staging buffer behind `upload_buffer`
+const STAGING_CHUNK_SIZE: DeviceSize = 16 * 1024 * 1024;
+
+const ENABLE_DIAGNOSTICS: bool = cfg!(debug_assertions);
+
+impl HelloTriangleApplication {
+    pub fn new() -> Result<Self, AppError> {
+        Self::with_config(AppConfig::default())
+    }
+
+    pub fn builder() -> AppBuilder {
+        AppBuilder::new()
+    }
+
+    pub fn with_config(config: AppConfig) -> Result<Self, AppError> {
+        let instance: Arc<Instance> = instance::create_instance(&config)?;
+        let (event_loop, surface) = Self::init_window(instance.clone(), &config)?;
+        Self::with_parts(instance, surface, Some(event_loop), config)
+    }
+
+    // For embedding into an application that already owns the window, surface and event loop.
+    // `run` isn't available then; the host forwards its events to `handle_event` instead.
+    pub fn with_surface(
+        instance: Arc<Instance>,
+        surface: Arc<Surface<Window>>,
+        config: AppConfig,
+    ) -> Result<Self, AppError> {
+        if !Arc::ptr_eq(surface.instance(), &instance) {
+            return Err(AppError::ForeignSurface);
+        }
+
+        let required_extensions = vulkano_win::required_extensions();
+        let enabled_extensions = instance.enabled_extensions();
+        if !enabled_extensions.is_superset_of(&required_extensions) {
+            let not_enabled = required_extensions.difference(enabled_extensions);
+            let missing = Vec::<CString>::from(&not_enabled)
+                .into_iter()
+                .map(|name| name.to_string_lossy().into_owned())
+                .collect();
+            return Err(AppError::SurfaceExtensionsNotEnabled(missing));
+        }
+
+        Self::with_parts(instance, surface, None, config)
+    }
+
+    fn with_parts(
+        instance: Arc<Instance>,
+        surface: Arc<Surface<Window>>,
+        event_loop: Option<EventLoop<()>>,
+        config: AppConfig,
+    ) -> Result<Self, AppError> {
+        let debug_callback = instance::setup_debug_callback(&instance, &config);
+        let physical_device_index = device::pick_physical_device(&instance, &surface, &config)?;
+        let (logical_device, graphics_queue, present_queue, compute_queue) =
+            device::create_logical_device(physical_device_index, &instance, &surface)?;
+        let (swapchain, images, image_format, image_extent) = swapchain::create_swap_chain(
+            physical_device_index,
+            &logical_device,
+            &instance,
+            &surface,
+            &config,
+        )?;
+        let depth_format = Self::find_depth_format(physical_device_index, &instance)?;
+        let msaa_samples =
+            Self::choose_sample_count(physical_device_index, &instance, config.msaa_samples);
+        let render_pass =
+            Self::create_render_pass(&logical_device, image_format, depth_format, msaa_samples);
+        let descriptor_set_layout = Self::create_descriptor_set_layout(&logical_device);
+        let graphics_pipeline = Self::create_graphics_pipeline(
+            &logical_device,
+            &render_pass,
+            &descriptor_set_layout,
+            config.vertex_shader_path.as_deref(),
+            config.fragment_shader_path.as_deref(),
+        )?;
+        let depth_image =
+            Self::create_depth_resources(&logical_device, depth_format, image_extent, msaa_samples);
+        let msaa_image =
+            Self::create_msaa_resources(&logical_device, image_format, image_extent, msaa_samples);
+        let framebuffers =
+            Self::create_framebuffers(&images, msaa_image.as_ref(), &depth_image, &render_pass);
+        let vertex_buffer = Self::create_vertex_buffer(&logical_device, &graphics_queue)?;
+        let index_buffer = Self::create_index_buffer(&logical_device, &graphics_queue)?;
+        let uniform_buffers = Self::create_uniform_buffers(&logical_device, images.len());
+        let texture_image = Self::create_texture_image(
+            &logical_device,
+            &graphics_queue,
+            config.texture_path.as_deref(),
+        )?;
+        let texture_sampler = Self::create_texture_sampler(&logical_device, config.max_anisotropy);
+        let descriptor_sets = Self::create_descriptor_sets(
+            &descriptor_set_layout,
+            &uniform_buffers,
+            &texture_image,
+            &texture_sampler,
+        );
+        let command_buffers = Self::create_command_buffers(
+            &logical_device,
+            &graphics_queue,
+            &framebuffers,
+            &graphics_pipeline,
+            &vertex_buffer,
+            &index_buffer,
+            &descriptor_sets,
+        );
+        let frames_in_flight = vec![None; MAX_FRAMES_IN_FLIGHT];
+        let images_in_flight = vec![None; images.len()];
+        let pipeline_dirty = Arc::new(AtomicBool::new(false));
+        let shader_watcher = Self::watch_shaders(&config, pipeline_dirty.clone());
+        // println!("Physical_Device: {:?}", physical_device);
+        // println!("Logical_Device: {:?}", logical_device);
+
+        if ENABLE_DIAGNOSTICS {
+            device::print_format_support(physical_device_index, &instance);
+        }
+
+        let clear_via_transfer = config.clear_via_transfer && swapchain.image_usage().transfer_dst;
+        if config.clear_via_transfer && !clear_via_transfer {
+            println!("Can't clear via transfer: swapchain images lack transfer_dst usage");
+        }
+
+        let current_monitor = surface.window().current_monitor();
+        let frame_interval = Self::frame_interval(config.frame_cap, current_monitor.as_ref());
+
+        let app = Self {
+            instance,
+            physical_device_index,
+            logical_device,
+            graphics_queue,
+            present_queue,
+            compute_queue,
+            swapchain,
+            images,
+            image_format,
+            image_extent,
+            render_pass,
+            depth_format,
+            msaa_samples,
+            swapchain_dirty: false,
+            minimized: false,
+            graphics_pipeline,
+            framebuffers,
+            command_buffers,
+            frames_in_flight,
+            images_in_flight,
+            current_frame: 0,
+            vertex_buffer,
+            index_buffer,
+            uniform_buffers,
+            descriptor_set_layout,
+            descriptor_sets,
+            texture_image,
+            texture_sampler,
+            start_time: Instant::now(),
+            frame_timings: FrameTimings::default(),
+            frame_stats: FrameStats::new(),
+            accumulated_timings: FrameTimings::default(),
+            timed_frames: 0,
+            debug_callback,
+            owns_window: event_loop.is_some(),
+            fatal_error: None,
+            event_loop,
+            surface,
+            clear_via_transfer,
+            paused: false,
+            paused_by_focus: false,
+            pipeline_dirty,
+            _shader_watcher: shader_watcher,
+            frame_interval,
+            current_monitor,
+            last_frame: Instant::now(),
+            config,
+        };
+
+        if ENABLE_DIAGNOSTICS {
+            println!(
+                "Supported device features:\n{}",
+                app.supported_features_report()
+            );
+        }
+
+        Ok(app)
+    }
+
+    pub fn enable_debug_messages(&mut self, on: bool) {
+        if !on {
+            self.debug_callback = None;
+            return;
+        }
+        if self.debug_callback.is_some() {
+            return;
+        }
+        if !self.instance.enabled_extensions().ext_debug_utils {
+            println!("Can't enable debug messages: instance was created without ext_debug_utils");
+            return;
+        }
+
+        self.debug_callback = Some(instance::create_debug_messenger(
+            &self.instance,
+            self.config.debug_severity,
+        ));
+    }
+
+    // Work submitted here runs concurrently with graphics when `has_async_compute` is true, so its
+    // results must be handed to graphics through a semaphore (e.g. joining the compute future
+    // before the graphics submission), and buffers shared by both queues need concurrent sharing
+    // or an ownership transfer. Without a separate family this is just the graphics queue.
+    pub fn compute_queue(&self) -> &Arc<Queue> {
+        &self.compute_queue
+    }
+
+    pub fn has_async_compute(&self) -> bool {
+        self.compute_queue.family().id() != self.graphics_queue.family().id()
+    }
+
+    // The driver may create more images than the `min_image_count` we request, so anything kept
+    // per swapchain image (framebuffers, recorded command buffers) must be sized from this. It is
+    // unrelated to how many frames the CPU lets run ahead of the GPU (frames in flight).
+    pub fn swapchain_image_count(&self) -> usize {
+        self.images.len()
+    }
+
+    pub fn format_support(&self, format: Format) -> FormatCapabilities {
+        let physical_device =
+            PhysicalDevice::from_index(&self.instance, self.physical_device_index).unwrap();
+        device::query_format_support(&physical_device, format)
+    }
+
+    pub fn supported_features_report(&self) -> String {
+        let physical_device =
+            PhysicalDevice::from_index(&self.instance, self.physical_device_index).unwrap();
+        let properties = physical_device.properties();
+        let features = physical_device.supported_features();
+
+        let notable_features = [
+            ("sampler_anisotropy", features.sampler_anisotropy),
+            ("geometry_shader", features.geometry_shader),
+            ("tessellation_shader", features.tessellation_shader),
+            ("fill_mode_non_solid", features.fill_mode_non_solid),
+            ("wide_lines", features.wide_lines),
+            ("logic_op", features.logic_op),
+            ("sample_rate_shading", features.sample_rate_shading),
+            ("multi_draw_indirect", features.multi_draw_indirect),
+            (
+                "draw_indirect_first_instance",
+                features.draw_indirect_first_instance,
+            ),
+            ("draw_indirect_count", features.draw_indirect_count),
+            ("descriptor_indexing", features.descriptor_indexing),
+            (
+                "runtime_descriptor_array",
+                features.runtime_descriptor_array,
+            ),
+            ("texture_compression_bc", features.texture_compression_bc),
+            (
+                "texture_compression_etc2",
+                features.texture_compression_etc2,
+            ),
+            (
+                "texture_compression_astc_ldr",
+                features.texture_compression_astc_ldr,
+            ),
+            ("sparse_binding", features.sparse_binding),
+            ("protected_memory", features.protected_memory),
+            ("timeline_semaphore", features.timeline_semaphore),
+        ];
+
+        let mut report = format!(
+            "{} ({:?}, Vulkan {}, driver {})\n",
+            properties.device_name,
+            properties.device_type,
+            properties.api_version,
+            properties.driver_version
+        );
+        for (name, supported) in notable_features {
+            let supported = if supported { "yes" } else { "no" };
+            report.push_str(&format!("- {}: {}\n", name, supported));
+        }
+
+        report
+    }
+
+    fn find_depth_format(
+        physical_device_index: usize,
+        instance: &Arc<Instance>,
+    ) -> Result<Format, AppError> {
+        let physical_device = PhysicalDevice::from_index(instance, physical_device_index).unwrap();
+        DEPTH_FORMATS
+            .into_iter()
+            .find(|&format| {
+                device::query_format_support(&physical_device, format)
+                    .optimal
+                    .depth_stencil_attachment
+            })
+            .ok_or(AppError::NoDepthFormat)
+    }
+
+    // Has to match the swapchain extent, so it's recreated along with the swapchain
+    fn create_depth_resources(
+        logical_device: &Arc<Device>,
+        depth_format: Format,
+        image_extent: [u32; 2],
+        samples: SampleCount,
+    ) -> Arc<ImageView<AttachmentImage>> {
+        let image = AttachmentImage::multisampled_with_usage(
+            logical_device.clone(),
+            image_extent,
+            samples,
+            depth_format,
+            ImageUsage {
+                depth_stencil_attachment: true,
+                ..ImageUsage::none()
+            },
+        )
+        .expect("Couldn't create depth image");
+        ImageView::new_default(image).expect("Couldn't create depth image view")
+    }
+
+    // Highest count usable for both color and depth attachments that doesn't exceed `target`
+    fn choose_sample_count(
+        physical_device_index: usize,
+        instance: &Arc<Instance>,
+        target: SampleCount,
+    ) -> SampleCount {
+        let physical_device = PhysicalDevice::from_index(instance, physical_device_index).unwrap();
+        let properties = physical_device.properties();
+        let color = properties.framebuffer_color_sample_counts;
+        let depth = properties.framebuffer_depth_sample_counts;
+
+        let candidates = [
+            (SampleCount::Sample64, color.sample64 && depth.sample64),
+            (SampleCount::Sample32, color.sample32 && depth.sample32),
+            (SampleCount::Sample16, color.sample16 && depth.sample16),
+            (SampleCount::Sample8, color.sample8 && depth.sample8),
+            (SampleCount::Sample4, color.sample4 && depth.sample4),
+            (SampleCount::Sample2, color.sample2 && depth.sample2),
+        ];
+        let samples = candidates
+            .into_iter()
+            .find(|&(samples, supported)| supported && samples as u32 <= target as u32)
+            .map_or(SampleCount::Sample1, |(samples, _)| samples);
+
+        if samples != target {
+            println!("Using {:?} instead of {:?} for MSAA", samples, target);
+        }
+        samples
+    }
+
+    // Multisampled color target that gets resolved into the swapchain image, None without MSAA
+    fn create_msaa_resources(
+        logical_device: &Arc<Device>,
+        image_format: Format,
+        image_extent: [u32; 2],
+        samples: SampleCount,
+    ) -> Option<Arc<ImageView<AttachmentImage>>> {
+        if samples == SampleCount::Sample1 {
+            return None;
+        }
+
+        let image = AttachmentImage::transient_multisampled(
+            logical_device.clone(),
+            image_extent,
+            samples,
+            image_format,
+        )
+        .expect("Couldn't create MSAA color image");
+        Some(ImageView::new_default(image).expect("Couldn't create MSAA color image view"))
+    }
+
+    // Attachments are ordered color, depth, then the resolve target when multisampling
+    fn create_render_pass(
+        logical_device: &Arc<Device>,
+        image_format: Format,
+        depth_format: Format,
+        samples: SampleCount,
+    ) -> Arc<RenderPass> {
+        if samples != SampleCount::Sample1 {
+            return vulkano::single_pass_renderpass!(
+                logical_device.clone(),
+                attachments: {
+                    msaa_color: {
+                        load: Clear,
+                        store: DontCare,
+                        format: image_format,
+                        samples: samples as u32,
+                    },
+                    depth: {
+                        load: Clear,
+                        store: DontCare,
+                        format: depth_format,
+                        samples: samples as u32,
+                    },
+                    color: {
+                        load: DontCare,
+                        store: Store,
+                        format: image_format,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [msaa_color],
+                    depth_stencil: {depth},
+                    resolve: [color]
+                }
+            )
+            .expect("Couldn't create render pass");
+        }
+
+        vulkano::single_pass_renderpass!(
+            logical_device.clone(),
+            attachments: {
+                color: {
+                    load: Clear,
+                    store: Store,
+                    format: image_format,
+                    samples: 1,
+                },
+                depth: {
+                    load: Clear,
+                    store: DontCare,
+                    format: depth_format,
+                    samples: 1,
+                }
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {depth}
+            }
+        )
+        .expect("Couldn't create render pass")
+    }
+
+    fn create_graphics_pipeline(
+        logical_device: &Arc<Device>,
+        render_pass: &Arc<RenderPass>,
+        descriptor_set_layout: &Arc<DescriptorSetLayout>,
+        vertex_shader_path: Option<&Path>,
+        fragment_shader_path: Option<&Path>,
+    ) -> Result<Arc<GraphicsPipeline>, AppError> {
+        let vert_shader_module = match vertex_shader_path {
+            Some(path) => load_shader_module(logical_device, path)?,
+            None => vertex_shader::load(logical_device.clone())
+                .expect("Couldn't create vertex shader module"),
+        };
+        let frag_shader_module = match fragment_shader_path {
+            Some(path) => load_shader_module(logical_device, path)?,
+            None => fragment_shader::load(logical_device.clone())
+                .expect("Couldn't create fragment shader module"),
+        };
+
+        let subpass = Subpass::from(render_pass.clone(), 0).unwrap();
+        let pipeline_layout = PipelineLayout::new(
+            logical_device.clone(),
+            PipelineLayoutCreateInfo {
+                set_layouts: vec![descriptor_set_layout.clone()],
+                push_constant_ranges: vec![PushConstantRange {
+                    stages: ShaderStages {
+                        vertex: true,
+                        ..ShaderStages::none()
+                    },
+                    offset: 0,
+                    size: mem::size_of::<PushConstants>() as u32,
+                }],
+            },
+        )
+        .expect("Couldn't create pipeline layout");
+
+        let vert_entry_point = vert_shader_module
+            .entry_point("main")
+            .ok_or_else(|| missing_entry_point(vertex_shader_path))?;
+        let frag_entry_point = frag_shader_module
+            .entry_point("main")
+            .ok_or_else(|| missing_entry_point(fragment_shader_path))?;
+
+        let pipeline = GraphicsPipeline::start()
+            .vertex_input_state(BuffersDefinition::new().vertex::<Vertex>())
+            .vertex_shader(vert_entry_point, ())
+            .input_assembly_state(InputAssemblyState::new())
+            .viewport_state(ViewportState::viewport_dynamic_scissor_dynamic(1))
+            .fragment_shader(frag_entry_point, ())
+            .rasterization_state(
+                RasterizationState::new()
+                    .cull_mode(CullMode::Back)
+                    .front_face(FrontFace::CounterClockwise),
+            )
+            .depth_stencil_state(DepthStencilState::simple_depth_test())
+            .multisample_state(MultisampleState {
+                rasterization_samples: subpass.num_samples().unwrap_or(SampleCount::Sample1),
+                ..Default::default()
+            })
+            .render_pass(subpass)
+            .with_pipeline_layout(logical_device.clone(), pipeline_layout)?;
+
+        Ok(pipeline)
+    }
+
+    fn create_framebuffers(
+        images: &[Arc<SwapchainImage<Window>>],
+        msaa_image: Option<&Arc<ImageView<AttachmentImage>>>,
+        depth_image: &Arc<ImageView<AttachmentImage>>,
+        render_pass: &Arc<RenderPass>,
+    ) -> Vec<Arc<Framebuffer>> {
+        images
+            .iter()
+            .map(|image| {
+                let view = ImageView::new_default(image.clone())
+                    .expect("Couldn't create swapchain image view");
+                Self::create_framebuffer(view, msaa_image, depth_image, render_pass)
+            })
+            .collect()
+    }
+
+    fn create_framebuffer(
+        view: Arc<dyn ImageViewAbstract>,
+        msaa_image: Option<&Arc<ImageView<AttachmentImage>>>,
+        depth_image: &Arc<ImageView<AttachmentImage>>,
+        render_pass: &Arc<RenderPass>,
+    ) -> Arc<Framebuffer> {
+        Framebuffer::new(
+            render_pass.clone(),
+            FramebufferCreateInfo {
+                attachments: match msaa_image {
+                    Some(msaa_image) => vec![msaa_image.clone(), depth_image.clone(), view],
+                    None => vec![view, depth_image.clone()],
+                },
+                ..Default::default()
+            },
+        )
+        .expect("Couldn't create framebuffer")
+    }
+
+    // The staging buffer only lives for the upload and is no larger than the data, so small
+    // uploads don't pay for a full STAGING_CHUNK_SIZE of host-visible memory
+    fn upload_buffer<T: Pod + Send + Sync>(
+        logical_device: &Arc<Device>,
+        queue: &Arc<Queue>,
+        data: &[T],
+        usage: BufferUsage,
+    ) -> Result<Arc<DeviceLocalBuffer<[T]>>, AppError> {
+        if data.is_empty() {
+            return Err(AppError::EmptyUpload);
+        }
+
+        let buffer = DeviceLocalBuffer::array(
+            logical_device.clone(),
+            data.len() as DeviceSize,
+            BufferUsage {
+                transfer_dst: true,
+                ..usage
+            },
+            [queue.family()],
+        )
+        .expect("Couldn't create device local buffer");
+
+        let chunk_len = cmp::min(
+            data.len() as DeviceSize,
+            STAGING_CHUNK_SIZE / mem::size_of::<T>() as DeviceSize,
+        );
+        StreamingUploader::new(logical_device.clone(), queue.clone(), chunk_len)
+            .upload(data, &buffer)?;
+        Ok(buffer)
+    }
+
+    fn create_vertex_buffer(
+        logical_device: &Arc<Device>,
+        queue: &Arc<Queue>,
+    ) -> Result<Arc<DeviceLocalBuffer<[Vertex]>>, AppError> {
+        Self::upload_buffer(
+            logical_device,
+            queue,
+            &VERTICES,
+            BufferUsage::vertex_buffer(),
+        )
+    }
+
+    fn create_index_buffer(
+        logical_device: &Arc<Device>,
+        queue: &Arc<Queue>,
+    ) -> Result<Arc<DeviceLocalBuffer<[u16]>>, AppError> {
+        Self::upload_buffer(logical_device, queue, &INDICES, BufferUsage::index_buffer())
+    }
+
+    fn create_uniform_buffers(
+        logical_device: &Arc<Device>,
+        image_count: usize,
+    ) -> Vec<Arc<CpuAccessibleBuffer<UniformBufferObject>>> {
+        (0..image_count)
+            .map(|_| {
+                CpuAccessibleBuffer::from_data(
+                    logical_device.clone(),
+                    BufferUsage::uniform_buffer(),
+                    false,
+                    UniformBufferObject::default(),
+                )
+                .expect("Couldn't create uniform buffer")
+            })
+            .collect()
+    }
+
+    fn create_texture_image(
+        logical_device: &Arc<Device>,
+        graphics_queue: &Arc<Queue>,
+        path: Option<&Path>,
+    ) -> Result<Arc<ImageView<ImmutableImage>>, AppError> {
+        let (pixels, width, height) = match path {
+            Some(path) => {
+                let texture =
+                    image::open(path).map_err(|err| AppError::TextureLoad(path.to_owned(), err))?;
+                // Expanding to RGBA8 keeps rows tightly packed whatever the width, so odd-sized
+                // and non-square images upload without row padding issues
+                let texture = texture.to_rgba8();
+                let (width, height) = texture.dimensions();
+                (texture.into_raw(), width, height)
+            }
+            // A single white texel leaves the vertex colors unchanged
+            None => (vec![255; 4], 1, 1),
+        };
+
+        // Copies through a staging buffer into a device local, optimally tiled image
+        let (texture, upload) = ImmutableImage::from_iter(
+            pixels,
+            ImageDimensions::Dim2d {
+                width,
+                height,
+                array_layers: 1,
+            },
+            MipmapsCount::One,
+            Format::R8G8B8A8_SRGB,
+            graphics_queue.clone(),
+        )
+        .expect("Couldn't create texture image");
+        upload
+            .then_signal_fence_and_flush()
+            .expect("Couldn't upload texture image")
+            .wait(None)
+            .unwrap();
+
+        Ok(ImageView::new_default(texture).expect("Couldn't create texture image view"))
+    }
+
+    fn create_texture_sampler(logical_device: &Arc<Device>, max_anisotropy: f32) -> Arc<Sampler> {
+        let anisotropy = if max_anisotropy <= 1.0 {
+            None
+        } else if logical_device.enabled_features().sampler_anisotropy {
+            let device_max = logical_device.physical_device().properties().max_sampler_anisotropy;
+            Some(max_anisotropy.min(device_max))
+        } else {
+            println!("Anisotropic filtering isn't supported by the device, disabling it");
+            None
+        };
+
+        Sampler::new(
+            logical_device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::Repeat; 3],
+                anisotropy,
+                ..Default::default()
+            },
+        )
+        .expect("Couldn't create texture sampler")
+    }
+
+    // Set 0 as seen by the shaders; new bindings (samplers, storage buffers) are added here and
+    // picked up by both the pipeline layout and the descriptor sets
+    fn create_descriptor_set_layout(logical_device: &Arc<Device>) -> Arc<DescriptorSetLayout> {
+        let mut bindings = BTreeMap::new();
+        bindings.insert(
+            0,
+            DescriptorSetLayoutBinding {
+                stages: ShaderStages {
+                    vertex: true,
+                    ..ShaderStages::none()
+                },
+                ..DescriptorSetLayoutBinding::descriptor_type(DescriptorType::UniformBuffer)
+            },
+        );
+        bindings.insert(
+            1,
+            DescriptorSetLayoutBinding {
+                stages: ShaderStages {
+                    fragment: true,
+                    ..ShaderStages::none()
+                },
+                ..DescriptorSetLayoutBinding::descriptor_type(DescriptorType::CombinedImageSampler)
+            },
+        );
+
+        DescriptorSetLayout::new(
+            logical_device.clone(),
+            DescriptorSetLayoutCreateInfo {
+                bindings,
+                ..Default::default()
+            },
+        )
+        .expect("Couldn't create descriptor set layout")
+    }
+
+    // One set per swapchain image, so they have to be rebuilt whenever the image count changes.
+    // Persistent sets are allocated once from the device's standard descriptor pool and reused
+    // every frame.
+    fn create_descriptor_sets(
+        layout: &Arc<DescriptorSetLayout>,
+        uniform_buffers: &[Arc<CpuAccessibleBuffer<UniformBufferObject>>],
+        texture_image: &Arc<ImageView<ImmutableImage>>,
+        texture_sampler: &Arc<Sampler>,
+    ) -> Vec<Arc<PersistentDescriptorSet>> {
+        uniform_buffers
+            .iter()
+            .map(|uniform_buffer| {
+                PersistentDescriptorSet::new(
+                    layout.clone(),
+                    [
+                        WriteDescriptorSet::buffer(0, uniform_buffer.clone()),
+                        WriteDescriptorSet::image_view_sampler(
+                            1,
+                            texture_image.clone(),
+                            texture_sampler.clone(),
+                        ),
+                    ],
+                )
+                .expect("Couldn't create descriptor set")
+            })
+            .collect()
+    }
+
+    // Rotation that undoes the presentation engine's pre-transform, so the scene shows upright
+    fn pre_rotation(transform: SurfaceTransform) -> Matrix4<f32> {
+        let angle = match transform {
+            SurfaceTransform::Rotate90 => 90.0,
+            SurfaceTransform::Rotate180 => 180.0,
+            SurfaceTransform::Rotate270 => 270.0,
+            _ => 0.0,
+        };
+        Matrix4::from_angle_z(Deg(angle))
+    }
+
+    fn uniform_buffer_object(
+        elapsed: f32,
+        image_extent: [u32; 2],
+        pre_transform: SurfaceTransform,
+    ) -> UniformBufferObject {
+        let [width, height] = image_extent;
+        let aspect_ratio = if swapchain::is_quarter_turn(pre_transform) {
+            height as f32 / width as f32
+        } else {
+            width as f32 / height as f32
+        };
+
+        let model = Matrix4::from_angle_z(Rad(elapsed * std::f32::consts::FRAC_PI_2));
+        let view = Matrix4::look_at_rh(
+            Point3::new(2.0, 2.0, 2.0),
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 1.0),
+        );
+        let mut proj = cgmath::perspective(Deg(45.0), aspect_ratio, 0.1, 10.0);
+        // Vulkan's clip space Y points down, unlike the OpenGL convention cgmath follows
+        proj.y.y *= -1.0;
+        let proj = Self::pre_rotation(pre_transform) * proj;
+
+        UniformBufferObject {
+            model: model.into(),
+            view: view.into(),
+            proj: proj.into(),
+        }
+    }
+
+    fn update_uniform_buffer(&self, image_index: usize) {
+        let ubo = Self::uniform_buffer_object(
+            self.start_time.elapsed().as_secs_f32(),
+            self.image_extent,
+            self.swapchain.pre_transform(),
+        );
+
+        // draw_frame has already waited for the frame that last read this image's buffer
+        let mut contents =
+            self.uniform_buffers[image_index].write().expect("Uniform buffer still in use");
+        *contents = ubo;
+    }
+
+    // Watches the parent directories of the configured shaders, since editors and compilers
+    // often replace a file instead of writing to it, which would drop a watch on the file itself
+    fn watch_shaders(config: &AppConfig, dirty: Arc<AtomicBool>) -> Option<RecommendedWatcher> {
+        let paths: Vec<&Path> = [&config.vertex_shader_path, &config.fragment_shader_path]
+            .into_iter()
+            .flatten()
+            .map(PathBuf::as_path)
+            .collect();
+        if paths.is_empty() {
+            return None;
+        }
+
+        let file_names: HashSet<_> =
+            paths.iter().filter_map(|path| path.file_name()).map(|name| name.to_owned()).collect();
+        let watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            if let Ok(event) = res {
+                let changed = event
+                    .paths
+                    .iter()
+                    .filter_map(|path| path.file_name())
+                    .any(|name| file_names.contains(name));
+                if changed && (event.kind.is_create() || event.kind.is_modify()) {
+                    dirty.store(true, Ordering::Release);
+                }
+            }
+        });
+
+        let directories: BTreeSet<&Path> = paths
+            .iter()
+            .map(|path| match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            })
+            .collect();
+        let result = watcher.and_then(|mut watcher| {
+            for directory in directories {
+                watcher.watch(directory, RecursiveMode::NonRecursive)?;
+            }
+            Ok(watcher)
+        });
+
+        match result {
+            Ok(watcher) => Some(watcher),
+            Err(err) => {
+                println!("Couldn't watch shaders, hot reloading is disabled: {}", err);
+                None
+            }
+        }
+    }
+
+    // Rebuilds the pipeline from the configured shaders. If they don't compile into a pipeline
+    // the old one stays in use, so a broken edit can simply be fixed and saved again.
+    fn recreate_pipeline(&mut self) {
+        // Let frames still using the old pipeline finish before it's replaced
+        for frame in 0..MAX_FRAMES_IN_FLIGHT {
+            self.wait_for_frame(frame);
+        }
+
+        let pipeline = Self::create_graphics_pipeline(
+            &self.logical_device,
+            &self.render_pass,
+            &self.descriptor_set_layout,
+            self.config.vertex_shader_path.as_deref(),
+            self.config.fragment_shader_path.as_deref(),
+        );
+        match pipeline {
+            Ok(pipeline) => {
+                self.graphics_pipeline = pipeline;
+                self.rebuild_command_buffers();
+                println!("Reloaded shaders");
+            }
+            Err(err) => println!("Keeping the previous pipeline: {}", err),
+        }
+    }
+
+    fn rebuild_command_buffers(&mut self) {
+        self.command_buffers = Self::create_command_buffers(
+            &self.logical_device,
+            &self.graphics_queue,
+            &self.framebuffers,
+            &self.graphics_pipeline,
+            &self.vertex_buffer,
+            &self.index_buffer,
+            &self.descriptor_sets,
+        );
+    }
+
+    // The current swapchain is handed to the driver as `old_swapchain`, so it can reuse its
+    // resources and keep presenting until the new images are ready. The old swapchain is retired
+    // by this; its images that are still acquired or queued stay valid until their futures
+    // complete, after which vulkano destroys it with the last Arc.
+    fn recreate_swap_chain(&mut self) {
+        let (capabilities, _, _) = swapchain::query_swap_chain_support(
+            self.physical_device_index,
+            &self.instance,
+            &self.surface,
+        );
+        let image_extent = match swapchain::choose_swap_extent(
+            &capabilities,
+            &self.surface,
+            self.swapchain.pre_transform(),
+        ) {
+            Ok(image_extent) => image_extent,
+            // Minimized, there's nothing to render to until the window is restored
+            Err(_) => {
+                self.minimized = true;
+                return;
+            }
+        };
+        self.minimized = false;
+
+        let (swapchain, images) = match self.swapchain.recreate(SwapchainCreateInfo {
+            image_extent,
+            ..self.swapchain.create_info()
+        }) {
+            Ok(recreated) => recreated,
+            // The window was resized again in the meantime, try again on the next frame
+            Err(SwapchainCreationError::ImageExtentNotSupported { .. }) => return,
+            Err(err) => {
+                self.fail(AppError::SwapchainCreation(err));
+                return;
+            }
+        };
+        self.swapchain = swapchain;
+        self.images = images;
+        self.image_extent = image_extent;
+        self.swapchain_dirty = false;
+
+        let depth_image = Self::create_depth_resources(
+            &self.logical_device,
+            self.depth_format,
+            image_extent,
+            self.msaa_samples,
+        );
+        let msaa_image = Self::create_msaa_resources(
+            &self.logical_device,
+            self.image_format,
+            image_extent,
+            self.msaa_samples,
+        );
+        self.framebuffers = Self::create_framebuffers(
+            &self.images,
+            msaa_image.as_ref(),
+            &depth_image,
+            &self.render_pass,
+        );
+
+        // The image count may have changed along with the swapchain
+        self.uniform_buffers =
+            Self::create_uniform_buffers(&self.logical_device, self.images.len());
+        self.descriptor_sets = Self::create_descriptor_sets(
+            &self.descriptor_set_layout,
+            &self.uniform_buffers,
+            &self.texture_image,
+            &self.texture_sampler,
+        );
+        self.images_in_flight = vec![None; self.images.len()];
+
+        // The viewport is dynamic state, so the pipeline survives and only the recording changes
+        self.rebuild_command_buffers();
+    }
+
+    fn create_command_buffers(
+        logical_device: &Arc<Device>,
+        graphics_queue: &Arc<Queue>,
+        framebuffers: &[Arc<Framebuffer>],
+        graphics_pipeline: &Arc<GraphicsPipeline>,
+        vertex_buffer: &Arc<DeviceLocalBuffer<[Vertex]>>,
+        index_buffer: &Arc<DeviceLocalBuffer<[u16]>>,
+        descriptor_sets: &[Arc<PersistentDescriptorSet>],
+    ) -> Vec<Arc<PrimaryAutoCommandBuffer>> {
+        framebuffers
+            .iter()
+            .zip(descriptor_sets)
+            .map(|(framebuffer, descriptor_set)| {
+                // The MSAA resolve target comes last and is fully overwritten, so isn't cleared
+                let mut clear_values = vec![CLEAR_COLOR.into(), 1.0.into()];
+                clear_values.resize(framebuffer.attachments().len(), ClearValue::None);
+
+                let [width, height] = framebuffer.extent();
+                let viewport = Viewport {
+                    origin: [0.0, 0.0],
+                    dimensions: [width as f32, height as f32],
+                    depth_range: 0.0..1.0,
+                };
+                let scissor = Scissor {
+                    origin: [0, 0],
+                    dimensions: [width, height],
+                };
+
+                let mut builder = AutoCommandBufferBuilder::primary(
+                    logical_device.clone(),
+                    graphics_queue.family(),
+                    CommandBufferUsage::SimultaneousUse,
+                )
+                .unwrap();
+
+                builder
+                    .begin_render_pass(framebuffer.clone(), SubpassContents::Inline, clear_values)
+                    .unwrap()
+                    .bind_pipeline_graphics(graphics_pipeline.clone())
+                    .set_viewport(0, [viewport])
+                    .set_scissor(0, [scissor])
+                    .bind_descriptor_sets(
+                        PipelineBindPoint::Graphics,
+                        graphics_pipeline.layout().clone(),
+                        0,
+                        descriptor_set.clone(),
+                    )
+                    .bind_vertex_buffers(0, vertex_buffer.clone())
+                    .bind_index_buffer(index_buffer.clone());
+
+                for offset in INSTANCE_OFFSETS {
+                    let push_constants = PushConstants {
+                        model: Matrix4::from_translation(Vector3::from(offset)).into(),
+                    };
+                    builder
+                        .push_constants(graphics_pipeline.layout().clone(), 0, push_constants)
+                        .draw_indexed(index_buffer.len() as u32, 1, 0, 0, 0)
+                        .unwrap();
+                }
+
+                builder.end_render_pass().unwrap();
+
+                Arc::new(builder.build().unwrap())
+            })
+            .collect()
+    }
+
+    fn wait_for_frame(&self, frame: usize) {
+        if let Some(fence) = &self.frames_in_flight[frame] {
+            fence.wait(None).expect("Couldn't wait for frame in flight");
+        }
+    }
+
+    fn draw_frame(&mut self) {
+        // This slot's previous submission has to finish before its resources are reused
+        self.wait_for_frame(self.current_frame);
+
+        let acquire_start = Instant::now();
+        let (image_index, suboptimal, acquire_future) =
+            match acquire_next_image(self.swapchain.clone(), None) {
+                Ok(acquired) => acquired,
+                Err(AcquireError::OutOfDate) => {
+                    self.swapchain_dirty = true;
+                    self.recreate_swap_chain();
+                    return;
+                }
+                Err(err) => {
+                    self.fail(AppError::SwapchainAcquire(err));
+                    return;
+                }
+            };
+        let acquire = acquire_start.elapsed();
+
+        // The image can still be presented, but the swapchain no longer matches the surface
+        // exactly; replace it before the next frame
+        if suboptimal {
+            self.swapchain_dirty = true;
+        }
+
+        // With more images than frame slots the acquired image may still be rendered to by
+        // another slot
+        if let Some(frame) = self.images_in_flight[image_index] {
+            self.wait_for_frame(frame);
+        }
+        self.images_in_flight[image_index] = Some(self.current_frame);
+
+        self.update_uniform_buffer(image_index);
+
+        let submit_start = Instant::now();
+        let previous_frame = (self.current_frame + MAX_FRAMES_IN_FLIGHT - 1) % MAX_FRAMES_IN_FLIGHT;
+        let previous_future = match self.frames_in_flight[previous_frame].clone() {
+            Some(fence) => fence.boxed(),
+            None => sync::now(self.logical_device.clone()).boxed(),
+        };
+
+        let command_buffer = self.command_buffers[image_index].clone();
+        let future = previous_future
+            .join(acquire_future)
+            .then_execute(self.graphics_queue.clone(), command_buffer)
+            .unwrap()
+            .then_swapchain_present(
+                self.present_queue.clone(),
+                self.swapchain.clone(),
+                image_index,
+            )
+            .boxed()
+            .then_signal_fence_and_flush();
+
+        self.frames_in_flight[self.current_frame] = match future {
+            Ok(future) => Some(Arc::new(future)),
+            Err(FlushError::OutOfDate) => {
+                self.swapchain_dirty = true;
+                None
+            }
+            Err(err) => {
+                println!("Failed to flush future: {:?}", err);
+                None
+            }
+        };
+        self.current_frame = (self.current_frame + 1) % MAX_FRAMES_IN_FLIGHT;
+
+        self.record_frame_timings(FrameTimings {
+            acquire,
+            submit_present: submit_start.elapsed(),
+        });
+
+        let stats_interval = self.config.stats_interval.filter(|_| self.owns_window);
+        if let Some(interval) = stats_interval {
+            if let Some(stats) = self.frame_stats.record(Instant::now(), interval) {
+                let title = format!("{} - {}", self.config.title, stats);
+                self.surface.window().set_title(&title);
+            }
+        }
+    }
+
+    // Renders a single frame into an offscreen image and reads it back as tightly packed RGBA8
+    // rows. The render pass is shared with the window, so the offscreen image takes the
+    // swapchain's format. Animation is pinned to its first frame so the output is reproducible.
+    pub fn render_to_image(&self, image_extent: [u32; 2]) -> Result<Vec<u8>, AppError> {
+        let swizzle = match self.image_format {
+            Format::R8G8B8A8_SRGB | Format::R8G8B8A8_UNORM => false,
+            Format::B8G8R8A8_SRGB | Format::B8G8R8A8_UNORM => true,
+            format => return Err(AppError::UnsupportedScreenshotFormat(format)),
+        };
+
+        let color_image = AttachmentImage::with_usage(
+            self.logical_device.clone(),
+            image_extent,
+            self.image_format,
+            ImageUsage {
+                color_attachment: true,
+                transfer_src: true,
+                ..ImageUsage::none()
+            },
+        )
+        .expect("Couldn't create offscreen image");
+        let view = ImageView::new_default(color_image.clone())
+            .expect("Couldn't create offscreen image view");
+        let depth_image = Self::create_depth_resources(
+            &self.logical_device,
+            self.depth_format,
+            image_extent,
+            self.msaa_samples,
+        );
+        let msaa_image = Self::create_msaa_resources(
+            &self.logical_device,
+            self.image_format,
+            image_extent,
+            self.msaa_samples,
+        );
+        let framebuffer =
+            Self::create_framebuffer(view, msaa_image.as_ref(), &depth_image, &self.render_pass);
+
+        let uniform_buffers = Self::create_uniform_buffers(&self.logical_device, 1);
+        *uniform_buffers[0].write().expect("Uniform buffer still in use") =
+            Self::uniform_buffer_object(0.0, image_extent, SurfaceTransform::Identity);
+        let descriptor_sets = Self::create_descriptor_sets(
+            &self.descriptor_set_layout,
+            &uniform_buffers,
+            &self.texture_image,
+            &self.texture_sampler,
+        );
+        let render_command_buffer = Self::create_command_buffers(
+            &self.logical_device,
+            &self.graphics_queue,
+            &[framebuffer],
+            &self.graphics_pipeline,
+            &self.vertex_buffer,
+            &self.index_buffer,
+            &descriptor_sets,
+        )
+        .remove(0);
+
+        let [width, height] = image_extent;
+        let readback_buffer = CpuAccessibleBuffer::from_iter(
+            self.logical_device.clone(),
+            BufferUsage::transfer_dst(),
+            false,
+            (0..width * height * 4).map(|_| 0u8),
+        )
+        .expect("Couldn't create readback buffer");
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            self.logical_device.clone(),
+            self.graphics_queue.family(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        builder
+            .copy_image_to_buffer(color_image, readback_buffer.clone())
+            .expect("Couldn't record offscreen image copy");
+        let copy_command_buffer = builder.build().unwrap();
+
+        sync::now(self.logical_device.clone())
+            .then_execute(self.graphics_queue.clone(), render_command_buffer)
+            .unwrap()
+            .then_execute(self.graphics_queue.clone(), copy_command_buffer)
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .expect("Couldn't submit offscreen render")
+            .wait(None)
+            .expect("Couldn't wait for offscreen render");
+
+        let mut pixels = readback_buffer.read().unwrap().to_vec();
+        if swizzle {
+            pixels.chunks_exact_mut(4).for_each(|pixel| pixel.swap(0, 2));
+        }
+        Ok(pixels)
+    }
+
+    // Renders a frame at the current swapchain extent and writes it out as a PNG
+    pub fn save_png(&self, path: &Path) -> Result<(), AppError> {
+        let [width, height] = self.image_extent;
+        let pixels = self.render_to_image(self.image_extent)?;
+        image::save_buffer(path, &pixels, width, height, image::ColorType::Rgba8)
+            .map_err(|err| AppError::ScreenshotSave(path.to_owned(), err))
+    }
+
+    pub fn descriptor_set_layout(&self) -> &Arc<DescriptorSetLayout> {
+        &self.descriptor_set_layout
+    }
+
+    pub fn frame_timings(&self) -> FrameTimings {
+        self.frame_timings
+    }
+
+    fn record_frame_timings(&mut self, timings: FrameTimings) {
+        self.frame_timings = timings;
+        if !ENABLE_DIAGNOSTICS {
+            return;
+        }
+
+        self.accumulated_timings.acquire += timings.acquire;
+        self.accumulated_timings.submit_present += timings.submit_present;
+        self.timed_frames += 1;
+        if self.timed_frames < TIMING_REPORT_INTERVAL {
+            return;
+        }
+
+        println!(
+            "Average over {} frames: acquire {:?}, submit+present {:?}",
+            self.timed_frames,
+            self.accumulated_timings.acquire / self.timed_frames,
+            self.accumulated_timings.submit_present / self.timed_frames
+        );
+        self.accumulated_timings = FrameTimings::default();
+        self.timed_frames = 0;
+    }
+
+    fn init_window(
+        instance: Arc<Instance>,
+        config: &AppConfig,
+    ) -> Result<(EventLoop<()>, Arc<Surface<Window>>), AppError> {
+        let event_loop = EventLoop::new();
+        let surface = WindowBuilder::new()
+            .with_title(&config.title)
+            .with_inner_size(LogicalSize::new(
+                f64::from(config.size[0]),
+                f64::from(config.size[1]),
+            ))
+            .with_resizable(config.resizable)
+            .with_decorations(config.decorations)
+            .with_visible(config.visible)
+            .build_vk_surface(&event_loop, instance)?;
+
+        Ok((event_loop, surface))
+    }
+
+    // Fills the next swapchain image with a solid color using only a transfer command, without
+    // any render pass or pipeline. Vulkano inserts the layout transitions to TransferDstOptimal
+    // and back to PresentSrc around the clear.
+    fn clear_swapchain_image(&mut self, color: [f32; 4]) {
+        let (image_index, suboptimal, acquire_future) =
+            match acquire_next_image(self.swapchain.clone(), None) {
+                Ok(acquired) => acquired,
+                Err(AcquireError::OutOfDate) => {
+                    self.swapchain_dirty = true;
+                    return;
+                }
+                Err(err) => {
+                    self.fail(AppError::SwapchainAcquire(err));
+                    return;
+                }
+            };
+        if suboptimal {
+            self.swapchain_dirty = true;
+        }
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            self.logical_device.clone(),
+            self.graphics_queue.family(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        builder
+            .clear_color_image(self.images[image_index].clone(), color.into())
+            .expect("Couldn't record swapchain clear");
+        let command_buffer = builder.build().unwrap();
+
+        let future = acquire_future
+            .then_execute(self.graphics_queue.clone(), command_buffer)
+            .unwrap()
+            .then_swapchain_present(
+                self.present_queue.clone(),
+                self.swapchain.clone(),
+                image_index,
+            )
+            .then_signal_fence_and_flush();
+        match future {
+            Ok(future) => future.wait(None).unwrap(),
+            Err(FlushError::OutOfDate) => self.swapchain_dirty = true,
+            Err(err) => self.fail(AppError::Present(err)),
+        }
+    }
+
+    // winit 0.26 only reports refresh rates per video mode, so take the fastest mode at the
+    // monitor's current resolution
+    fn monitor_refresh_rate(monitor: &MonitorHandle) -> Option<u32> {
+        let size = monitor.size();
+        monitor
+            .video_modes()
+            .filter(|mode| mode.size() == size)
+            .map(|mode| mode.refresh_rate() as u32)
+            .max()
+            .filter(|&hz| hz > 0)
+    }
+
+    fn frame_interval(frame_cap: FrameCap, monitor: Option<&MonitorHandle>) -> Option<Duration> {
+        match frame_cap {
+            FrameCap::Uncapped => None,
+            FrameCap::MonitorRefresh { margin_hz } => {
+                let refresh_rate = Self::monitor_refresh_rate(monitor?)?;
+                let target_hz = cmp::max(refresh_rate.saturating_sub(margin_hz), 1);
+                Some(Duration::from_secs(1) / target_hz)
+            }
+        }
+    }
+
+    fn update_frame_cap(&mut self) {
+        let monitor = self.surface.window().current_monitor();
+        if monitor == self.current_monitor {
+            return;
+        }
+
+        self.frame_interval = Self::frame_interval(self.config.frame_cap, monitor.as_ref());
+        self.current_monitor = monitor;
+        if let Some(interval) = self.frame_interval {
+            println!("Frame interval is now {:?}", interval);
+        }
+    }
+
+    // Returns when the next frame may start if the frame cap says it is too early to draw
+    fn frame_cap_deadline(&self) -> Option<Instant> {
+        let next_frame = self.last_frame + self.frame_interval?;
+        if Instant::now() < next_frame {
+            Some(next_frame)
+        } else {
+            None
+        }
+    }
+
+    // Stops issuing draws until `resume`. Events are still handled meanwhile, so a resize while
+    // paused marks the swapchain for recreation before the first frame after resuming.
+    pub fn pause(&mut self) {
+        if self.paused {
+            return;
+        }
+
+        for frame in 0..MAX_FRAMES_IN_FLIGHT {
+            self.wait_for_frame(frame);
+        }
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.paused_by_focus = false;
+    }
+
+    // The control flow is only touched where rendering needs it: Poll while drawing, Wait while
+    // paused or minimized, WaitUntil for the frame cap and Exit when our own window is closed.
+    // Hosts embedding the renderer through with_surface keep control otherwise.
+    fn fail(&mut self, err: AppError) {
+        if self.fatal_error.is_none() {
+            self.fatal_error = Some(err);
+        }
+    }
+
+    // For hosts driving handle_event themselves: the error that made it request Exit, if any
+    pub fn take_fatal_error(&mut self) -> Option<AppError> {
+        self.fatal_error.take()
+    }
+
+    pub fn handle_event(&mut self, event: Event<()>, control_flow: &mut ControlFlow) {
+        if self.fatal_error.is_some() {
+            *control_flow = ControlFlow::Exit;
+            return;
+        }
+
+        // The custom handler runs first and sees every event, including other windows' ones
+        if let Some(event_handler) = self.config.event_handler.as_mut() {
+            if event_handler(&event, control_flow) {
+                return;
+            }
+        }
+
+        if let Event::WindowEvent { window_id, .. } = &event {
+            if *window_id != self.surface.window().id() {
+                return;
+            }
+        }
+
+        match event {
+            Event::WindowEvent {
+                event: WindowEvent::CloseRequested,
+                ..
+            } if self.owns_window => *control_flow = ControlFlow::Exit,
+            Event::WindowEvent {
+                event: WindowEvent::Moved(_),
+                ..
+            } => self.update_frame_cap(),
+            Event::WindowEvent {
+                event: WindowEvent::Resized(_),
+                ..
+            } => self.swapchain_dirty = true,
+            Event::WindowEvent {
+                event: WindowEvent::Focused(focused),
+                ..
+            } if self.config.pause_on_focus_loss => {
+                if !focused && !self.paused {
+                    self.pause();
+                    self.paused_by_focus = true;
+                } else if focused && self.paused_by_focus {
+                    self.resume();
+                }
+            }
+            Event::RedrawEventsCleared if self.paused => *control_flow = ControlFlow::Wait,
+            Event::RedrawEventsCleared => {
+                if let Some(deadline) = self.frame_cap_deadline() {
+                    *control_flow = ControlFlow::WaitUntil(deadline);
+                    return;
+                }
+                *control_flow = ControlFlow::Poll;
+                self.last_frame = Instant::now();
+
+                if self.pipeline_dirty.swap(false, Ordering::Acquire) {
+                    self.recreate_pipeline();
+                }
+                if self.swapchain_dirty {
+                    self.recreate_swap_chain();
+                    if self.swapchain_dirty {
+                        // Polling a minimized window would only spin; restoring it sends a
+                        // Resized event that wakes the loop up again
+                        if self.minimized {
+                            *control_flow = ControlFlow::Wait;
+                        }
+                        return;
+                    }
+                }
+
+                if self.clear_via_transfer {
+                    self.clear_swapchain_image(CLEAR_COLOR)
+                } else {
+                    self.draw_frame()
+                }
+            }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F12),
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => {
+                self.enable_debug_messages(self.debug_callback.is_none());
+                let state = if self.debug_callback.is_some() {
+                    "on"
+                } else {
+                    "off"
+                };
+                println!("Debug messages {}", state);
+            }
+            _ => (),
+        }
+    }
+
+    // On desktop platforms the event loop returns once the window is closed, so the app is
+    // dropped normally (releasing its Vulkan objects) and errors can be handed back to the caller.
+    #[cfg(any(
+        target_os = "windows",
+        target_os = "macos",
+        target_os = "linux",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    ))]
+    pub fn run(mut self) -> Result<(), AppError> {
+        self.main_loop()
+    }
+
+    // Elsewhere winit can't return from the event loop: the process exits when the loop ends,
+    // so this never returns and Drop is not run.
+    #[cfg(not(any(
+        target_os = "windows",
+        target_os = "macos",
+        target_os = "linux",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    )))]
+    pub fn run(mut self) -> Result<(), AppError> {
+        let event_loop = self.event_loop.take().ok_or(AppError::NoEventLoop)?;
+        event_loop.run(move |event, _window_target, control_flow| {
+            // The process exits with the loop, so report the error here
+            if let Event::LoopDestroyed = event {
+                if let Some(err) = &self.fatal_error {
+                    eprintln!("Error: {}", err);
+                }
+            }
+            self.handle_event(event, control_flow)
+        })
+    }
+
+    #[cfg(any(
+        target_os = "windows",
+        target_os = "macos",
+        target_os = "linux",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    ))]
+    pub fn main_loop(&mut self) -> Result<(), AppError> {
+        use winit::platform::run_return::EventLoopExtRunReturn;
+
+        let mut event_loop = self.event_loop.take().ok_or(AppError::NoEventLoop)?;
+        event_loop.run_return(|event, _window_target, control_flow| {
+            self.handle_event(event, control_flow)
+        });
+        match self.fatal_error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for HelloTriangleApplication {
+    fn drop(&mut self) {
+        // Fields are dropped in declaration order after this, and every other Vulkan object keeps
+        // the instance alive through its Arc. The messenger is the one object the validation layer
+        // expects to be gone before the instance, so destroy it explicitly first.
+        self.debug_callback = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use vulkano::{
+        buffer::{BufferUsage, CpuAccessibleBuffer, DeviceLocalBuffer},
+        command_buffer::{AutoCommandBufferBuilder, CommandBufferUsage, PrimaryCommandBuffer},
+        device::{physical::PhysicalDevice, Device, DeviceCreateInfo, Queue, QueueCreateInfo},
+        instance::{Instance, InstanceCreateInfo},
+        sync::GpuFuture,
+    };
+
+    use super::{AppError, StreamingUploader};
+
+    // Any device with a graphics queue; run with --ignored where a driver (e.g. lavapipe) exists
+    fn test_device() -> Option<(Arc<Device>, Arc<Queue>)> {
+        let instance = Instance::new(InstanceCreateInfo::default()).ok()?;
+        let physical_device = PhysicalDevice::enumerate(&instance).next()?;
+        let family = physical_device.queue_families().find(|family| family.supports_graphics())?;
+        let (device, mut queues) = Device::new(
+            physical_device,
+            DeviceCreateInfo {
+                queue_create_infos: vec![QueueCreateInfo::family(family)],
+                ..Default::default()
+            },
+        )
+        .ok()?;
+        let queue = queues.next()?;
+        Some((device, queue))
+    }
+
+    fn destination(
+        device: &Arc<Device>,
+        queue: &Arc<Queue>,
+        len: u64,
+    ) -> Arc<DeviceLocalBuffer<[u8]>> {
+        DeviceLocalBuffer::array(
+            device.clone(),
+            len,
+            BufferUsage {
+                transfer_src: true,
+                transfer_dst: true,
+                ..BufferUsage::none()
+            },
+            [queue.family()],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn upload_larger_than_chunk() {
+        let (device, queue) = test_device().expect("No Vulkan device found");
+
+        // Not a multiple of the chunk length, so the last chunk is partial
+        let data: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        let destination = destination(&device, &queue, data.len() as u64);
+        StreamingUploader::new(device.clone(), queue.clone(), 64)
+            .upload(&data, &destination)
+            .unwrap();
+
+        let readback = CpuAccessibleBuffer::from_iter(
+            device.clone(),
+            BufferUsage::transfer_dst(),
+            false,
+            data.iter().map(|_| 0u8),
+        )
+        .unwrap();
+        let mut builder = AutoCommandBufferBuilder::primary(
+            device.clone(),
+            queue.family(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        builder.copy_buffer(destination, readback.clone()).unwrap();
+        builder
+            .build()
+            .unwrap()
+            .execute(queue)
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+
+        assert_eq!(&readback.read().unwrap()[..], &data[..]);
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn upload_rejects_empty_and_oversized_data() {
+        let (device, queue) = test_device().expect("No Vulkan device found");
+
+        let destination = destination(&device, &queue, 16);
+        let uploader = StreamingUploader::new(device, queue, 8);
+        assert!(matches!(
+            uploader.upload(&[], &destination),
+            Err(AppError::EmptyUpload)
+        ));
+        assert!(matches!(
+            uploader.upload(&[0; 17], &destination),
+            Err(AppError::UploadTooLarge(17, 16))
+        ));
+    }
+}
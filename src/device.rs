@@ -0,0 +1,531 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    sync::Arc,
+};
+
+use vulkano::{
+    device::{
+        physical::{PhysicalDevice, PhysicalDeviceType},
+        Device, DeviceCreateInfo, DeviceExtensions, Features, Queue, QueueCreateInfo,
+    },
+    format::{Format, FormatFeatures},
+    instance::Instance,
+    swapchain::Surface,
+};
+use winit::window::Window;
+
+use crate::{
+    app::{AppConfig, AppError},
+    swapchain,
+};
+
+pub struct QueueFamilyIndices {
+    graphics_family_id: Option<u32>,
+    presentation_family_id: Option<u32>,
+    // A compute-capable family other than the graphics one, used for async compute if present
+    compute_family_id: Option<u32>,
+}
+
+impl QueueFamilyIndices {
+    fn new() -> Self {
+        Self {
+            graphics_family_id: None,
+            presentation_family_id: None,
+            compute_family_id: None,
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.graphics_family_id.is_some() && self.presentation_family_id.is_some()
+    }
+
+    pub fn graphics(&self) -> Result<u32, AppError> {
+        self.graphics_family_id.ok_or(AppError::MissingQueueFamily("graphics"))
+    }
+
+    pub fn presentation(&self) -> Result<u32, AppError> {
+        self.presentation_family_id.ok_or(AppError::MissingQueueFamily("presentation"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TilingCapabilities {
+    pub sampled: bool,
+    pub color_attachment: bool,
+    pub depth_stencil_attachment: bool,
+    pub storage: bool,
+    pub blit_src: bool,
+    pub blit_dst: bool,
+    pub linear_filtering: bool,
+}
+
+impl TilingCapabilities {
+    fn from_features(features: &FormatFeatures) -> Self {
+        Self {
+            sampled: features.sampled_image,
+            color_attachment: features.color_attachment,
+            depth_stencil_attachment: features.depth_stencil_attachment,
+            storage: features.storage_image,
+            blit_src: features.blit_src,
+            blit_dst: features.blit_dst,
+            linear_filtering: features.sampled_image_filter_linear,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FormatCapabilities {
+    pub format: Format,
+    pub optimal: TilingCapabilities,
+    pub linear: TilingCapabilities,
+}
+
+impl FormatCapabilities {
+    fn is_supported(&self) -> bool {
+        let TilingCapabilities {
+            sampled,
+            color_attachment,
+            depth_stencil_attachment,
+            storage,
+            ..
+        } = self.optimal;
+        sampled || color_attachment || depth_stencil_attachment || storage
+    }
+}
+
+// Formats the renderer may pick for swapchain, depth and offscreen images
+const DIAGNOSTIC_FORMATS: [Format; 7] = [
+    Format::B8G8R8A8_SRGB,
+    Format::B8G8R8A8_UNORM,
+    Format::R8G8B8A8_SRGB,
+    Format::R16G16B16A16_SFLOAT,
+    Format::D32_SFLOAT,
+    Format::D32_SFLOAT_S8_UINT,
+    Format::D24_UNORM_S8_UINT,
+];
+
+// Devices that fall short of any of these are rejected during device selection
+#[derive(Clone, Copy, Debug)]
+pub struct RequiredLimits {
+    pub min_max_image_dimension2_d: u32,
+    pub min_max_push_constants_size: u32,
+    pub min_max_bound_descriptor_sets: u32,
+}
+
+impl Default for RequiredLimits {
+    fn default() -> Self {
+        Self {
+            min_max_image_dimension2_d: 4096,
+            min_max_push_constants_size: 128,
+            min_max_bound_descriptor_sets: 4,
+        }
+    }
+}
+
+struct LimitShortfall {
+    limit: &'static str,
+    required: u32,
+    actual: u32,
+}
+
+impl fmt::Display for LimitShortfall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} is {} but {} is required (short by {})",
+            self.limit,
+            self.actual,
+            self.required,
+            self.required - self.actual
+        )
+    }
+}
+
+impl RequiredLimits {
+    fn shortfalls(&self, physical_device: &PhysicalDevice) -> Vec<LimitShortfall> {
+        let properties = physical_device.properties();
+        let checks = [
+            (
+                "max_image_dimension2_d",
+                self.min_max_image_dimension2_d,
+                properties.max_image_dimension2_d,
+            ),
+            (
+                "max_push_constants_size",
+                self.min_max_push_constants_size,
+                properties.max_push_constants_size,
+            ),
+            (
+                "max_bound_descriptor_sets",
+                self.min_max_bound_descriptor_sets,
+                properties.max_bound_descriptor_sets,
+            ),
+        ];
+
+        checks
+            .into_iter()
+            .filter(|&(_, required, actual)| actual < required)
+            .map(|(limit, required, actual)| LimitShortfall {
+                limit,
+                required,
+                actual,
+            })
+            .collect()
+    }
+}
+
+// Devices lacking any of these are rejected; nothing the app draws needs one yet
+fn required_device_features() -> Features {
+    Features::none()
+}
+
+// Enabled only where supported, callers check `enabled_features()` before relying on them
+fn optional_device_features() -> Features {
+    Features {
+        sampler_anisotropy: true,
+        ..Features::none()
+    }
+}
+
+pub fn find_queue_family_ids(
+    physical_device: &PhysicalDevice,
+    surface: &Arc<Surface<Window>>,
+) -> QueueFamilyIndices {
+    let families: Vec<FamilySupport> = physical_device
+        .queue_families()
+        .map(|family| FamilySupport {
+            id: family.id(),
+            graphics: family.supports_graphics(),
+            compute: family.supports_compute(),
+            present: family
+                .supports_surface(surface)
+                .expect("Error while checking Surface drawing support"),
+        })
+        .collect();
+
+    select_queue_families(&families)
+}
+
+// What a queue family offers, as far as family selection is concerned
+struct FamilySupport {
+    id: u32,
+    graphics: bool,
+    compute: bool,
+    present: bool,
+}
+
+fn select_queue_families(families: &[FamilySupport]) -> QueueFamilyIndices {
+    let mut family_ids = QueueFamilyIndices::new();
+
+    for family in families {
+        if family.graphics {
+            family_ids.graphics_family_id = Some(family.id)
+        }
+        if family.present {
+            family_ids.presentation_family_id = Some(family.id)
+        }
+        if family_ids.is_complete() {
+            break;
+        }
+    }
+
+    // Prefer a dedicated compute family (no graphics) since it's the most likely to overlap
+    let compute_families: Vec<_> = families
+        .iter()
+        .filter(|family| family.compute && Some(family.id) != family_ids.graphics_family_id)
+        .collect();
+    family_ids.compute_family_id = compute_families
+        .iter()
+        .find(|family| !family.graphics)
+        .or_else(|| compute_families.first())
+        .map(|family| family.id);
+
+    family_ids
+}
+
+// Scores a device for selection, or explains why it can't run the app at all. Any suitable device
+// works, but dedicated GPUs are preferred over integrated ones, which beat software renderers.
+fn rate_device_suitability(
+    physical_device: &PhysicalDevice,
+    instance: &Arc<Instance>,
+    surface: &Arc<Surface<Window>>,
+    required_limits: &RequiredLimits,
+) -> Result<u32, String> {
+    let properties = physical_device.properties();
+    let missing_features =
+        required_device_features().difference(physical_device.supported_features());
+    let supported_extensions = physical_device.supported_extensions();
+    let queue_family_ids = find_queue_family_ids(physical_device, surface);
+
+    if !supported_extensions.khr_swapchain {
+        return Err(String::from("VK_KHR_swapchain is not supported"));
+    }
+    if !queue_family_ids.is_complete() {
+        return Err(String::from(
+            "no queue families for both graphics and presentation",
+        ));
+    }
+
+    let (_capabilities, formats, present_modes) =
+        swapchain::query_swap_chain_support(physical_device.index(), instance, surface);
+    if formats.is_empty() || present_modes.is_empty() {
+        return Err(String::from(
+            "the surface offers no formats or present modes",
+        ));
+    }
+
+    if missing_features != Features::none() {
+        return Err(format!("missing features {:?}", missing_features));
+    }
+
+    let shortfalls = required_limits.shortfalls(physical_device);
+    if !shortfalls.is_empty() {
+        let shortfalls: Vec<String> = shortfalls.iter().map(ToString::to_string).collect();
+        return Err(shortfalls.join(", "));
+    }
+
+    Ok(match properties.device_type {
+        PhysicalDeviceType::DiscreteGpu => 1000,
+        PhysicalDeviceType::IntegratedGpu => 500,
+        PhysicalDeviceType::VirtualGpu => 250,
+        PhysicalDeviceType::Cpu => 100,
+        PhysicalDeviceType::Other => 10,
+    })
+}
+
+pub fn pick_physical_device(
+    instance: &Arc<Instance>,
+    surface: &Arc<Surface<Window>>,
+    config: &AppConfig,
+) -> Result<usize, AppError> {
+    let mut rejections = Vec::new();
+    let mut suitable_devices = Vec::new();
+    for device in PhysicalDevice::enumerate(instance) {
+        match rate_device_suitability(&device, instance, surface, &config.required_limits) {
+            Ok(mut score) => {
+                // Larger than any type score, so the preference only reorders suitable devices
+                if Some(device.properties().device_type) == config.device_preference {
+                    score += 10_000;
+                }
+                suitable_devices.push((device, score));
+            }
+            Err(reason) => {
+                println!("Rejecting {}: {}", device.properties().device_name, reason);
+                rejections.push(format!("{}: {}", device.properties().device_name, reason));
+            }
+        }
+    }
+
+    let (suitable_device, _score) = suitable_devices
+        .into_iter()
+        .max_by_key(|&(_, score)| score)
+        .ok_or(AppError::NoSuitableDevice(rejections))?;
+
+    Ok(suitable_device.index())
+}
+
+// Looks queues up by the family they were created from rather than by position, since graphics
+// and present may share a family and collapse into a single queue. Without a separate compute
+// family, compute work goes to the graphics queue.
+fn assign_queues<Q: Clone>(
+    family_ids: &QueueFamilyIndices,
+    queues: &BTreeMap<u32, Q>,
+) -> Result<(Q, Q, Q), AppError> {
+    let queue_for_family =
+        |id: u32| queues.get(&id).cloned().ok_or(AppError::InvalidQueueFamily(id));
+
+    let graphics_queue = queue_for_family(family_ids.graphics()?)?;
+    let present_queue = queue_for_family(family_ids.presentation()?)?;
+    let compute_queue = match family_ids.compute_family_id {
+        Some(id) => queue_for_family(id)?,
+        None => graphics_queue.clone(),
+    };
+
+    Ok((graphics_queue, present_queue, compute_queue))
+}
+
+pub fn create_logical_device(
+    physical_device_index: usize,
+    instance: &Arc<Instance>,
+    surface: &Arc<Surface<Window>>,
+) -> Result<(Arc<Device>, Arc<Queue>, Arc<Queue>, Arc<Queue>), AppError> {
+    let physical_device = PhysicalDevice::from_index(instance, physical_device_index)
+        .expect("Couldn't retrieve physical device by index while creating logical device");
+
+    let queue_family_ids = find_queue_family_ids(&physical_device, surface);
+
+    // One queue per distinct family, in a fixed order so queue creation is deterministic
+    let unique_family_ids: BTreeSet<u32> = vec![
+        queue_family_ids.graphics_family_id,
+        queue_family_ids.presentation_family_id,
+        queue_family_ids.compute_family_id,
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    let queue_create_infos = unique_family_ids
+        .into_iter()
+        .map(|id| {
+            physical_device
+                .queue_family_by_id(id)
+                .map(QueueCreateInfo::family)
+                .ok_or(AppError::InvalidQueueFamily(id))
+        })
+        .collect::<Result<_, _>>()?;
+
+    // pick_physical_device has already rejected devices lacking a required feature
+    let supported_features = physical_device.supported_features();
+    let enabled_features = required_device_features()
+        .union(&optional_device_features().intersection(supported_features));
+
+    let mut device_extensions = DeviceExtensions::none();
+    device_extensions.khr_swapchain = true;
+
+    let (device, queues) = Device::new(
+        physical_device,
+        DeviceCreateInfo {
+            enabled_extensions: device_extensions,
+            enabled_features,
+            queue_create_infos,
+            ..Default::default()
+        },
+    )?;
+
+    let queues: BTreeMap<u32, Arc<Queue>> =
+        queues.map(|queue| (queue.family().id(), queue)).collect();
+    let (graphics_queue, present_queue, compute_queue) = assign_queues(&queue_family_ids, &queues)?;
+
+    // Don't trust the family selection blindly: presenting from a family that can't present
+    // to this surface is invalid usage and may only show up as a driver crash much later
+    let present_family = present_queue.family();
+    if !present_family.supports_surface(surface).unwrap_or(false) {
+        return Err(AppError::PresentQueueUnsupported(present_family.id()));
+    }
+
+    Ok((device, graphics_queue, present_queue, compute_queue))
+}
+
+pub fn query_format_support(
+    physical_device: &PhysicalDevice,
+    format: Format,
+) -> FormatCapabilities {
+    let properties = physical_device.format_properties(format);
+
+    FormatCapabilities {
+        format,
+        optimal: TilingCapabilities::from_features(&properties.optimal_tiling_features),
+        linear: TilingCapabilities::from_features(&properties.linear_tiling_features),
+    }
+}
+
+pub fn print_format_support(physical_device_index: usize, instance: &Arc<Instance>) {
+    let physical_device = PhysicalDevice::from_index(instance, physical_device_index).unwrap();
+
+    println!(
+        "Format support on {}:",
+        physical_device.properties().device_name
+    );
+    for format in DIAGNOSTIC_FORMATS {
+        let capabilities = query_format_support(&physical_device, format);
+        if !capabilities.is_supported() {
+            println!("  {:?}: unsupported", format);
+            continue;
+        }
+        println!("  {:?}", format);
+        println!("    optimal: {:?}", capabilities.optimal);
+        println!("    linear:  {:?}", capabilities.linear);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{assign_queues, select_queue_families, FamilySupport, QueueFamilyIndices};
+    use crate::app::AppError;
+
+    fn family(id: u32, graphics: bool, compute: bool, present: bool) -> FamilySupport {
+        FamilySupport {
+            id,
+            graphics,
+            compute,
+            present,
+        }
+    }
+
+    #[test]
+    fn select_single_universal_family() {
+        let family_ids = select_queue_families(&[family(0, true, true, true)]);
+        assert!(matches!(family_ids.graphics(), Ok(0)));
+        assert!(matches!(family_ids.presentation(), Ok(0)));
+        assert_eq!(family_ids.compute_family_id, None);
+    }
+
+    #[test]
+    fn select_separate_present_family() {
+        let family_ids =
+            select_queue_families(&[family(0, true, true, false), family(1, false, false, true)]);
+        assert!(matches!(family_ids.graphics(), Ok(0)));
+        assert!(matches!(family_ids.presentation(), Ok(1)));
+    }
+
+    #[test]
+    fn select_prefers_dedicated_compute_family() {
+        let family_ids = select_queue_families(&[
+            family(0, true, true, true),
+            family(1, true, true, false),
+            family(2, false, true, false),
+        ]);
+        assert!(matches!(family_ids.graphics(), Ok(0)));
+        assert_eq!(family_ids.compute_family_id, Some(2));
+    }
+
+    #[test]
+    fn select_without_present_family() {
+        let family_ids = select_queue_families(&[family(0, true, true, false)]);
+        assert!(!family_ids.is_complete());
+        assert!(matches!(
+            family_ids.presentation(),
+            Err(AppError::MissingQueueFamily("presentation"))
+        ));
+    }
+
+    fn family_ids(graphics: Option<u32>, presentation: Option<u32>) -> QueueFamilyIndices {
+        QueueFamilyIndices {
+            graphics_family_id: graphics,
+            presentation_family_id: presentation,
+            compute_family_id: None,
+        }
+    }
+
+    #[test]
+    fn assign_queues_shared_family() {
+        let queues = BTreeMap::from([(0, "shared")]);
+        let assigned = assign_queues(&family_ids(Some(0), Some(0)), &queues);
+        assert!(matches!(assigned, Ok(("shared", "shared", "shared"))));
+    }
+
+    #[test]
+    fn assign_queues_out_of_range_family() {
+        let queues = BTreeMap::from([(0, "graphics")]);
+        let assigned = assign_queues(&family_ids(Some(0), Some(5)), &queues);
+        assert!(matches!(assigned, Err(AppError::InvalidQueueFamily(5))));
+    }
+
+    #[test]
+    fn assign_queues_missing_family() {
+        let queues = BTreeMap::from([(0, "graphics")]);
+        let assigned = assign_queues(&family_ids(None, Some(0)), &queues);
+        assert!(matches!(
+            assigned,
+            Err(AppError::MissingQueueFamily("graphics"))
+        ));
+        let assigned = assign_queues(&family_ids(Some(0), None), &queues);
+        assert!(matches!(
+            assigned,
+            Err(AppError::MissingQueueFamily("presentation"))
+        ));
+    }
+}
@@ -0,0 +1,218 @@
+use std::{collections::HashSet, ffi::CString, sync::Arc};
+
+use vulkano::instance::{
+    debug::{
+        DebugUtilsMessageSeverity, DebugUtilsMessageType, DebugUtilsMessenger,
+        DebugUtilsMessengerCreateInfo,
+    },
+    layers_list, Instance, InstanceCreateInfo, InstanceExtensions, ValidationFeatureEnable,
+};
+
+use crate::app::{AppConfig, AppError};
+
+// Extra VK_EXT_validation_features modes, only applied when validation is enabled and the
+// layer exposes the extension. All off keeps the basic validation set.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ValidationFeatureConfig {
+    pub gpu_assisted: bool,
+    pub best_practices: bool,
+    pub synchronization: bool,
+}
+
+fn required_extensions(config: &AppConfig) -> Result<InstanceExtensions, AppError> {
+    let surface_extensions = vulkano_win::required_extensions(); // already has surface caps 2
+    let mut extensions = surface_extensions;
+    if config.validation {
+        extensions.ext_debug_utils = true;
+    }
+
+    let supported_extensions = InstanceExtensions::supported_by_core()
+        .map_err(|err| AppError::VulkanUnavailable(err.to_string()))?;
+
+    if !supported_extensions.is_superset_of(&surface_extensions) {
+        let not_supported = surface_extensions.difference(&supported_extensions);
+        let missing = Vec::<CString>::from(&not_supported)
+            .into_iter()
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect();
+        return Err(AppError::MissingSurfaceExtensions(missing));
+    }
+
+    // Validation still works without debug utils, its messages just aren't routed to us.
+    // setup_debug_callback checks for the extension and skips the messenger.
+    if extensions.ext_debug_utils && !supported_extensions.ext_debug_utils {
+        println!("VK_EXT_debug_utils isn't supported, validation messages won't be printed");
+        extensions.ext_debug_utils = false;
+    }
+
+    if config.validation && !validation_features(config).is_empty() {
+        match InstanceExtensions::supported_by_layer("VK_LAYER_KHRONOS_validation") {
+            Ok(layer_extensions) if layer_extensions.ext_validation_features => {
+                extensions.ext_validation_features = true;
+            }
+            _ => {
+                println!("Validation features requested but ext_validation_features is unavailable")
+            }
+        }
+    }
+
+    Ok(extensions)
+}
+
+fn validation_features(config: &AppConfig) -> Vec<ValidationFeatureEnable> {
+    let mut features = Vec::new();
+    if config.validation_features.gpu_assisted {
+        features.push(ValidationFeatureEnable::GpuAssisted);
+    }
+    if config.validation_features.best_practices {
+        features.push(ValidationFeatureEnable::BestPractices);
+    }
+    if config.validation_features.synchronization {
+        features.push(ValidationFeatureEnable::SynchronizationValidation);
+    }
+    features
+}
+
+fn validation_layers(config: &AppConfig) -> Vec<std::string::String> {
+    let mut layers: Vec<std::string::String> = Vec::new();
+    if config.validation {
+        layers.push("VK_LAYER_KHRONOS_validation".into());
+    }
+    if layers.is_empty() {
+        return layers;
+    }
+
+    let available_layers: HashSet<String> = match layers_list() {
+        Ok(available_layers) => available_layers.map(|layer| layer.name().to_owned()).collect(),
+        Err(err) => {
+            println!("Couldn't retrieve layers list, enabling no layers: {}", err);
+            return Vec::new();
+        }
+    };
+
+    layers
+        .into_iter()
+        .filter(|layer| {
+            let available = available_layers.contains(layer);
+            if !available {
+                println!(
+                    "Layer {} is not available (is the Vulkan SDK installed?), skipping it. \
+                     Run with --list-layers to see what is installed",
+                    layer
+                );
+            }
+            available
+        })
+        .collect()
+}
+
+pub fn print_available_layers() {
+    let layers = match layers_list() {
+        Ok(layers) => layers,
+        Err(err) => {
+            println!("Couldn't retrieve layers list: {}", err);
+            return;
+        }
+    };
+
+    println!("Available instance layers ({}):", layers.len());
+    for layer in layers {
+        println!(
+            "- {} (Vulkan {}, implementation {}): {}",
+            layer.name(),
+            layer.vulkan_version(),
+            layer.implementation_version(),
+            layer.description()
+        );
+    }
+}
+
+pub fn setup_debug_callback(
+    instance: &Arc<Instance>,
+    config: &AppConfig,
+) -> Option<DebugUtilsMessenger> {
+    if !config.validation {
+        return None;
+    }
+    // A caller-provided instance may have been created without debug utils
+    if !instance.enabled_extensions().ext_debug_utils {
+        println!("Validation requested but VK_EXT_debug_utils isn't enabled on the instance");
+        return None;
+    }
+
+    Some(create_debug_messenger(instance, config.debug_severity))
+}
+
+fn severity_name(severity: DebugUtilsMessageSeverity) -> &'static str {
+    if severity.error {
+        "ERROR"
+    } else if severity.warning {
+        "WARNING"
+    } else if severity.information {
+        "INFO"
+    } else {
+        "VERBOSE"
+    }
+}
+
+fn message_type_name(ty: DebugUtilsMessageType) -> &'static str {
+    if ty.validation {
+        "VALIDATION"
+    } else if ty.performance {
+        "PERFORMANCE"
+    } else {
+        "GENERAL"
+    }
+}
+
+pub fn create_debug_messenger(
+    instance: &Arc<Instance>,
+    message_severity: DebugUtilsMessageSeverity,
+) -> DebugUtilsMessenger {
+    let callback = unsafe {
+        DebugUtilsMessenger::new(
+            instance.clone(),
+            DebugUtilsMessengerCreateInfo {
+                message_severity,
+                message_type: DebugUtilsMessageType::all(),
+                ..DebugUtilsMessengerCreateInfo::user_callback(Arc::new(|msg| {
+                    println!(
+                        "[{}][{}] {}: {}",
+                        severity_name(msg.severity),
+                        message_type_name(msg.ty),
+                        msg.layer_prefix.unwrap_or("<no layer>"),
+                        msg.description
+                    );
+                }))
+            },
+        )
+        .expect("Couldn't create Debug Utils Messenger")
+    };
+
+    // let callback = DebugCallback::new(instance, message_severity, message_type, |msg| {
+    //     println!("{:?}", msg.description);
+    // })
+    // .expect("Couldn't create DebugCallback");
+    callback
+}
+
+pub fn create_instance(config: &AppConfig) -> Result<Arc<Instance>, AppError> {
+    /* Create instance */
+    let enabled_extensions = required_extensions(config)?;
+    let enabled_validation_features = if enabled_extensions.ext_validation_features {
+        validation_features(config)
+    } else {
+        Vec::new()
+    };
+
+    let instance = Instance::new(InstanceCreateInfo {
+        application_name: Some(config.title.clone()),
+        enabled_extensions,
+        enabled_layers: validation_layers(config),
+        enabled_validation_features,
+        // max_api_version: Some(Version::V1_3),
+        ..Default::default()
+    })?;
+
+    Ok(instance)
+}
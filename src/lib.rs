@@ -0,0 +1,10 @@
+mod app;
+mod device;
+mod instance;
+mod swapchain;
+
+pub use app::{
+    AppBuilder, AppConfig, AppError, EventHandler, FrameCap, FrameTimings, HelloTriangleApplication,
+};
+pub use device::{FormatCapabilities, RequiredLimits, TilingCapabilities};
+pub use instance::{print_available_layers, ValidationFeatureConfig};
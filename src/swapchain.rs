@@ -0,0 +1,338 @@
+use std::{cmp, sync::Arc};
+
+use vulkano::{
+    device::{physical::PhysicalDevice, Device},
+    format::Format,
+    image::{ImageUsage, SwapchainImage},
+    instance::Instance,
+    swapchain::{
+        ColorSpace, PresentMode, Surface, SurfaceCapabilities, SurfaceInfo, SurfaceTransform,
+        Swapchain, SwapchainCreateInfo,
+    },
+    sync::Sharing,
+};
+use winit::{dpi::PhysicalSize, window::Window};
+
+use crate::{
+    app::{AppConfig, AppError},
+    device,
+};
+
+fn clamp<T: Ord>(val: T, min: T, max: T) -> T {
+    cmp::max(cmp::min(val, max), min)
+}
+
+// Clamps a window size into the surface's extent range. Drivers can report degenerate ranges
+// (e.g. a zero max while minimized), so an inverted range collapses onto `min` and a zero result
+// means there's nothing to render to.
+fn clamp_extent(size: [u32; 2], min: [u32; 2], max: [u32; 2]) -> Option<[u32; 2]> {
+    let mut extent = [0; 2];
+    for axis in 0..2 {
+        let max = cmp::max(min[axis], max[axis]);
+        extent[axis] = clamp(size[axis], min[axis], max);
+    }
+
+    if extent.contains(&0) {
+        None
+    } else {
+        Some(extent)
+    }
+}
+
+pub fn query_swap_chain_support(
+    physical_device_index: usize,
+    instance: &Arc<Instance>,
+    surface: &Arc<Surface<Window>>,
+) -> (
+    SurfaceCapabilities,
+    Vec<(Format, ColorSpace)>,
+    Vec<PresentMode>,
+) {
+    let physical_device = PhysicalDevice::from_index(instance, physical_device_index).unwrap();
+    let surface_info = SurfaceInfo::default();
+    let capabilities = physical_device.surface_capabilities(surface, surface_info.clone()).unwrap();
+    let formats = physical_device.surface_formats(surface, surface_info).unwrap();
+    let present_modes = physical_device.surface_present_modes(surface).unwrap();
+
+    return (capabilities, formats, present_modes.collect());
+}
+
+fn choose_swap_surface_format(available_formats: &[(Format, ColorSpace)]) -> (Format, ColorSpace) {
+    available_formats
+        .iter()
+        .find(|&&format| format.0 == Format::B8G8R8A8_SRGB && format.1 == ColorSpace::SrgbNonLinear)
+        .unwrap_or(&available_formats[0])
+        .to_owned()
+}
+
+fn force_swap_surface_format(
+    available_formats: &[(Format, ColorSpace)],
+    forced: (Format, ColorSpace),
+) -> Result<(Format, ColorSpace), AppError> {
+    if !available_formats.contains(&forced) {
+        return Err(AppError::UnsupportedSwapchainFormat(forced.0, forced.1));
+    }
+
+    let preferred = choose_swap_surface_format(available_formats);
+    if preferred != forced {
+        println!(
+            "Warning: forcing swapchain format {:?} instead of {:?}",
+            forced, preferred
+        );
+    }
+    Ok(forced)
+}
+
+fn choose_swap_present_modes(
+    available_modes: &[PresentMode],
+    preferred_modes: &[PresentMode],
+) -> PresentMode {
+    preferred_modes
+        .iter()
+        .find(|&mode| available_modes.contains(mode))
+        .unwrap_or(&PresentMode::Fifo)
+        .to_owned()
+}
+
+fn choose_surface_transform(
+    capabilities: &SurfaceCapabilities,
+    config: &AppConfig,
+) -> Result<SurfaceTransform, AppError> {
+    let supported = &capabilities.supported_transforms;
+    let current = capabilities.current_transform;
+    match config.surface_transform {
+        Some(transform) if is_mirrored(transform) => {
+            Err(AppError::UnsupportedSurfaceTransform(transform))
+        }
+        Some(transform) if supported.supports(transform) => Ok(transform),
+        Some(transform) => Err(AppError::UnsupportedSurfaceTransform(transform)),
+        None if is_mirrored(current) && supported.supports(SurfaceTransform::Identity) => {
+            Ok(SurfaceTransform::Identity)
+        }
+        None if is_mirrored(current) => Err(AppError::UnsupportedSurfaceTransform(current)),
+        None => Ok(current),
+    }
+}
+
+// The projection only undoes rotations. A mirrored image would stay mirrored and flip the
+// triangle winding that back-face culling relies on, so these transforms aren't used.
+fn is_mirrored(transform: SurfaceTransform) -> bool {
+    matches!(
+        transform,
+        SurfaceTransform::HorizontalMirror
+            | SurfaceTransform::HorizontalMirrorRotate90
+            | SurfaceTransform::HorizontalMirrorRotate180
+            | SurfaceTransform::HorizontalMirrorRotate270
+    )
+}
+
+pub fn is_quarter_turn(transform: SurfaceTransform) -> bool {
+    matches!(
+        transform,
+        SurfaceTransform::Rotate90
+            | SurfaceTransform::Rotate270
+            | SurfaceTransform::HorizontalMirrorRotate90
+            | SurfaceTransform::HorizontalMirrorRotate270
+    )
+}
+
+pub fn choose_swap_extent(
+    capabilities: &SurfaceCapabilities,
+    surface: &Arc<Surface<Window>>,
+    pre_transform: SurfaceTransform,
+) -> Result<[u32; 2], AppError> {
+    let PhysicalSize { width, height } = surface.window().inner_size();
+    // Images are in the display's native orientation, which a quarter turn swaps with the
+    // window's
+    let size = if is_quarter_turn(pre_transform) {
+        [height, width]
+    } else {
+        [width, height]
+    };
+    clamp_extent(
+        size,
+        capabilities.min_image_extent,
+        capabilities.max_image_extent,
+    )
+    .ok_or(AppError::UnusableSurfaceExtent([width, height]))
+}
+
+// Names of the usage flags in `requested` that `supported` lacks
+fn unsupported_usage(requested: &ImageUsage, supported: &ImageUsage) -> Vec<&'static str> {
+    let flags = [
+        (
+            "transfer_src",
+            requested.transfer_src,
+            supported.transfer_src,
+        ),
+        (
+            "transfer_dst",
+            requested.transfer_dst,
+            supported.transfer_dst,
+        ),
+        ("sampled", requested.sampled, supported.sampled),
+        ("storage", requested.storage, supported.storage),
+        (
+            "color_attachment",
+            requested.color_attachment,
+            supported.color_attachment,
+        ),
+        (
+            "depth_stencil_attachment",
+            requested.depth_stencil_attachment,
+            supported.depth_stencil_attachment,
+        ),
+        (
+            "transient_attachment",
+            requested.transient_attachment,
+            supported.transient_attachment,
+        ),
+        (
+            "input_attachment",
+            requested.input_attachment,
+            supported.input_attachment,
+        ),
+    ];
+    flags
+        .into_iter()
+        .filter(|&(_, requested, supported)| requested && !supported)
+        .map(|(name, _, _)| name)
+        .collect()
+}
+
+pub fn create_swap_chain(
+    physical_device_index: usize,
+    logical_device: &Arc<Device>,
+    instance: &Arc<Instance>,
+    surface: &Arc<Surface<Window>>,
+    config: &AppConfig,
+) -> Result<
+    (
+        Arc<Swapchain<Window>>,
+        Vec<Arc<SwapchainImage<Window>>>,
+        Format,
+        [u32; 2],
+    ),
+    AppError,
+> {
+    let (capabilities, formats, present_modes) =
+        query_swap_chain_support(physical_device_index, instance, surface);
+    let (image_format, image_color_space) = match config.force_swapchain_format {
+        Some(forced) => force_swap_surface_format(&formats, forced)?,
+        None => choose_swap_surface_format(&formats),
+    };
+    let present_mode = choose_swap_present_modes(&present_modes, &config.present_modes);
+    let pre_transform = choose_surface_transform(&capabilities, config)?;
+    let image_extent = choose_swap_extent(&capabilities, surface, pre_transform)?;
+
+    let min_image_count = capabilities.min_image_count + 1;
+    let min_image_count = if capabilities.max_image_count.is_some()
+        && min_image_count > capabilities.max_image_count.unwrap()
+    {
+        capabilities.max_image_count.unwrap()
+    } else {
+        min_image_count
+    };
+
+    let supported_usage = &capabilities.supported_usage_flags;
+    let mut image_usage = ImageUsage {
+        color_attachment: true,
+        ..config.swapchain_image_usage
+    };
+    let unsupported = unsupported_usage(&image_usage, supported_usage);
+    if !unsupported.is_empty() {
+        return Err(AppError::UnsupportedSwapchainUsage(unsupported));
+    }
+    // Lets swapchain images be written by transfer commands. Only an optimization for the
+    // clear, so the app falls back to drawing without it.
+    if config.clear_via_transfer {
+        if supported_usage.transfer_dst {
+            image_usage.transfer_dst = true;
+        } else {
+            println!("Surface doesn't support transfer_dst swapchain images, leaving it out");
+        }
+    }
+
+    let composite_alpha = capabilities.supported_composite_alpha.iter().next().unwrap();
+
+    let physical_device = PhysicalDevice::from_index(instance, physical_device_index).unwrap();
+    let queue_family_ids = device::find_queue_family_ids(&physical_device, surface);
+
+    let graphics_family_id = queue_family_ids.graphics()?;
+    let presentation_family_id = queue_family_ids.presentation()?;
+    let image_sharing = if graphics_family_id == presentation_family_id {
+        Sharing::Exclusive
+    } else {
+        Sharing::Concurrent([graphics_family_id, presentation_family_id][..].into())
+    };
+
+    // Create the swapchain and its images.
+    let (swapchain, images) = Swapchain::new(
+        logical_device.clone(),
+        surface.clone(),
+        SwapchainCreateInfo {
+            min_image_count,
+            image_format: Some(image_format),
+            image_color_space,
+            image_extent,
+            image_usage,
+            pre_transform,
+            composite_alpha,
+            present_mode,
+            image_sharing,
+            ..Default::default()
+        },
+    )?;
+
+    Ok((swapchain, images, image_format, image_extent))
+}
+
+#[cfg(test)]
+mod tests {
+    use vulkano::image::ImageUsage;
+
+    use super::{clamp_extent, unsupported_usage};
+
+    #[test]
+    fn clamp_extent_fixed_range() {
+        assert_eq!(
+            clamp_extent([800, 600], [1024, 768], [1024, 768]),
+            Some([1024, 768])
+        );
+    }
+
+    #[test]
+    fn clamp_extent_zero_max_while_minimized() {
+        assert_eq!(clamp_extent([800, 600], [0, 0], [0, 0]), None);
+    }
+
+    #[test]
+    fn clamp_extent_inverted_range_collapses_onto_min() {
+        assert_eq!(
+            clamp_extent([800, 600], [100, 200], [50, 50]),
+            Some([100, 200])
+        );
+    }
+
+    #[test]
+    fn clamp_extent_zero_window_size() {
+        assert_eq!(clamp_extent([0, 0], [0, 0], [4096, 4096]), None);
+        assert_eq!(clamp_extent([800, 0], [0, 0], [4096, 4096]), None);
+    }
+
+    #[test]
+    fn unsupported_usage_names_missing_flags() {
+        let supported = ImageUsage {
+            color_attachment: true,
+            transfer_dst: true,
+            ..ImageUsage::none()
+        };
+        let requested = ImageUsage {
+            color_attachment: true,
+            storage: true,
+            ..ImageUsage::none()
+        };
+        assert_eq!(unsupported_usage(&requested, &supported), ["storage"]);
+        assert!(unsupported_usage(&supported, &supported).is_empty());
+    }
+}